@@ -0,0 +1,29 @@
+// Compiles every shader in src/shaders to SPIR-V so the binary doesn't need the GLSL files at runtime
+// The results are written to OUT_DIR with .spv appended to their name, e.g. shader.vs.spv
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+fn main() {
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    println!("cargo:rerun-if-changed=src/shaders");
+
+    for entry in fs::read_dir("src/shaders").expect("Could not read src/shaders") {
+        let path = entry.unwrap().path();
+        let shader_type = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("vs") => glsl_to_spirv::ShaderType::Vertex,
+            Some("fs") => glsl_to_spirv::ShaderType::Fragment,
+            _ => continue,
+        };
+        println!("cargo:rerun-if-changed={}", path.display());
+
+        let source = fs::read_to_string(&path).unwrap();
+        // A shader that doesn't compile fails the build with the compiler's message
+        let mut spirv_file = glsl_to_spirv::compile(&source, shader_type).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+        let mut spirv = vec![];
+        spirv_file.read_to_end(&mut spirv).unwrap();
+
+        let file_name = path.file_name().unwrap().to_str().unwrap();
+        fs::write(Path::new(&out_dir).join(format!("{}.spv", file_name)), spirv).unwrap();
+    }
+}
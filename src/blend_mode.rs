@@ -0,0 +1,59 @@
+// How drawn colors are combined with the colors already in the frame
+// Every blend mode needs its own render pipeline, so changing it flushes the current batch
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum BlendMode {
+    // Alpha blending of non premultiplied colors, the frame's alpha is replaced
+    Normal,
+    // Adds the color weighted by its alpha, used for glows and light
+    Additive,
+    // Multiplies the frame with the color, used for shadows and tinting
+    Multiply,
+    // Inverse of Multiply, brightens the frame
+    Screen,
+    // Alpha blending of colors that are already multiplied by their alpha
+    PremultipliedAlpha,
+    // Overwrites the frame, alpha included
+    Opaque,
+}
+
+impl BlendMode {
+    pub fn get_color_blend(self) -> wgpu::BlendDescriptor {
+        let (src_factor, dst_factor) = match self {
+            BlendMode::Normal => (wgpu::BlendFactor::SrcAlpha, wgpu::BlendFactor::OneMinusSrcAlpha),
+            BlendMode::Additive => (wgpu::BlendFactor::SrcAlpha, wgpu::BlendFactor::One),
+            BlendMode::Multiply => (wgpu::BlendFactor::DstColor, wgpu::BlendFactor::Zero),
+            BlendMode::Screen => (wgpu::BlendFactor::One, wgpu::BlendFactor::OneMinusSrcColor),
+            BlendMode::PremultipliedAlpha => (wgpu::BlendFactor::One, wgpu::BlendFactor::OneMinusSrcAlpha),
+            BlendMode::Opaque => (wgpu::BlendFactor::One, wgpu::BlendFactor::Zero),
+        };
+        wgpu::BlendDescriptor {
+            src_factor,
+            dst_factor,
+            operation: wgpu::BlendOperation::Add,
+        }
+    }
+
+    pub fn get_alpha_blend(self) -> wgpu::BlendDescriptor {
+        match self {
+            BlendMode::Normal | BlendMode::Opaque => wgpu::BlendDescriptor::REPLACE,
+            // Glows stay visible on transparent targets
+            BlendMode::Additive => wgpu::BlendDescriptor {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+            // The frame's alpha is kept
+            BlendMode::Multiply | BlendMode::Screen => wgpu::BlendDescriptor {
+                src_factor: wgpu::BlendFactor::Zero,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+            BlendMode::PremultipliedAlpha => wgpu::BlendDescriptor {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                operation: wgpu::BlendOperation::Add,
+            },
+        }
+    }
+}
@@ -0,0 +1,171 @@
+// A rectangle in pixels; the origin is the top left corner of the frame
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ScissorRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl ScissorRect {
+    // Rectangles that don't overlap give an empty rectangle
+    pub fn intersect(self, other: ScissorRect) -> ScissorRect {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = self.get_right().min(other.get_right());
+        let bottom = self.get_bottom().min(other.get_bottom());
+        ScissorRect {
+            x,
+            y,
+            width: right.saturating_sub(x),
+            height: bottom.saturating_sub(y),
+        }
+    }
+
+    pub fn contains(self, x: u32, y: u32) -> bool {
+        x >= self.x && y >= self.y && x < self.get_right() && y < self.get_bottom()
+    }
+
+    // Rectangles reaching past u32::MAX end there
+    fn get_right(self) -> u32 {
+        self.x.saturating_add(self.width)
+    }
+
+    fn get_bottom(self) -> u32 {
+        self.y.saturating_add(self.height)
+    }
+}
+
+// Everything a draw is clipped by
+// Masks are indices into the renderer's list of mask shapes, a pixel has to be inside all of them
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ClipState {
+    pub scissor: Option<ScissorRect>,
+    pub masks: Vec<usize>,
+}
+
+// Remembers every clip state used during a frame so queued draws can refer to them by id
+// Id 0 is always the unclipped state
+pub struct ClipStack {
+    states: Vec<ClipState>,
+    stack: Vec<usize>,
+}
+
+impl ClipStack {
+    pub fn new() -> Self {
+        Self {
+            states: vec![ClipState::default()],
+            stack: vec![],
+        }
+    }
+
+    // Id of the state used by the next draw
+    pub fn current(&self) -> usize {
+        *self.stack.last().unwrap_or(&0)
+    }
+
+    pub fn get(&self, id: usize) -> &ClipState {
+        &self.states[id]
+    }
+
+    // Nested scissor rectangles are intersected
+    pub fn push_scissor(&mut self, rect: ScissorRect) {
+        let mut state = self.get(self.current()).clone();
+        state.scissor = Some(match state.scissor {
+            Some(scissor) => scissor.intersect(rect),
+            None => rect,
+        });
+        self.push(state);
+    }
+
+    pub fn push_mask(&mut self, mask: usize) {
+        let mut state = self.get(self.current()).clone();
+        state.masks.push(mask);
+        self.push(state);
+    }
+
+    fn push(&mut self, state: ClipState) {
+        self.states.push(state);
+        self.stack.push(self.states.len() - 1);
+    }
+
+    // Removes the last pushed scissor rectangle or mask
+    // Returns false if nothing was pushed, the stack stays unclipped then
+    pub fn pop(&mut self) -> bool {
+        self.stack.pop().is_some()
+    }
+
+    // Forgets the states of the frame, anything still pushed is popped
+    pub fn reset(&mut self) {
+        self.states.truncate(1);
+        self.stack.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: u32, y: u32, width: u32, height: u32) -> ScissorRect {
+        ScissorRect { x, y, width, height }
+    }
+
+    #[test]
+    fn intersect_overlapping() {
+        assert_eq!(rect(0, 0, 10, 10).intersect(rect(5, 2, 10, 4)), rect(5, 2, 5, 4));
+        assert_eq!(rect(5, 2, 10, 4).intersect(rect(0, 0, 10, 10)), rect(5, 2, 5, 4));
+    }
+
+    #[test]
+    fn intersect_contained() {
+        assert_eq!(rect(0, 0, 100, 100).intersect(rect(10, 20, 30, 40)), rect(10, 20, 30, 40));
+    }
+
+    #[test]
+    fn intersect_disjoint_is_empty() {
+        let intersection = rect(0, 0, 10, 10).intersect(rect(20, 0, 10, 10));
+        assert_eq!((intersection.width, intersection.height), (0, 10));
+        assert!(!intersection.contains(20, 0));
+        let touching = rect(0, 0, 10, 10).intersect(rect(0, 10, 10, 10));
+        assert_eq!(touching.height, 0);
+    }
+
+    #[test]
+    fn contains_excludes_right_and_bottom_edges() {
+        let scissor = rect(2, 3, 4, 5);
+        assert!(scissor.contains(2, 3) && scissor.contains(5, 7));
+        assert!(!scissor.contains(6, 3) && !scissor.contains(2, 8) && !scissor.contains(1, 3));
+    }
+
+    #[test]
+    fn rect_reaching_past_u32_max() {
+        let huge = rect(u32::MAX - 5, u32::MAX - 5, 10, 10);
+        assert!(huge.contains(u32::MAX - 1, u32::MAX - 5));
+        assert!(!huge.contains(u32::MAX - 6, u32::MAX - 1));
+        assert_eq!(huge.intersect(rect(0, 0, u32::MAX, u32::MAX)), rect(u32::MAX - 5, u32::MAX - 5, 5, 5));
+        let frame = rect(0, 0, 800, 600);
+        assert_eq!(frame.intersect(huge).width, 0);
+        assert_eq!(frame.intersect(rect(10, 20, u32::MAX, u32::MAX)), rect(10, 20, 790, 580));
+    }
+
+    #[test]
+    fn nested_scissors_intersect() {
+        let mut clip_stack = ClipStack::new();
+        clip_stack.push_scissor(rect(0, 0, 10, 10));
+        clip_stack.push_mask(0);
+        clip_stack.push_scissor(rect(5, 5, 10, 10));
+        let state = clip_stack.get(clip_stack.current());
+        assert_eq!(state.scissor, Some(rect(5, 5, 5, 5)));
+        assert_eq!(state.masks, [0]);
+    }
+
+    #[test]
+    fn unbalanced_pop_is_ignored() {
+        let mut clip_stack = ClipStack::new();
+        assert!(!clip_stack.pop());
+        clip_stack.push_scissor(rect(0, 0, 1, 1));
+        assert!(clip_stack.pop());
+        assert!(!clip_stack.pop());
+        assert_eq!(clip_stack.current(), 0);
+    }
+}
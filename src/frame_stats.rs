@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+// Why a batch was sent to the gpu
+// All textures share one texture array, so running out of texture slots never flushes a batch
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum FlushReason {
+    // The blend mode, material or clip changed between draws
+    StateChange,
+    VertexCapacity,
+    IndexCapacity,
+    // end_render drew what was left
+    EndOfFrame,
+}
+
+// What the renderer did during a frame, or during several frames when they are added together
+#[derive(Clone, Debug, Default)]
+pub struct FrameStats {
+    pub frames: u32,
+    pub flushes: HashMap<FlushReason, u32>,
+    // Including clip masks and every draw of a mesh
+    pub vertices: u64,
+    pub indices: u64,
+    // Distinct texture layers that were drawn from, summed over the frames
+    pub textures: u32,
+    // Time spent queueing draws and putting them into batches
+    pub draw_time: Duration,
+    // Time spent sending batches to the gpu
+    pub render_time: Duration,
+}
+
+impl FrameStats {
+    pub fn get_flushes(&self, reason: FlushReason) -> u32 {
+        self.flushes.get(&reason).copied().unwrap_or(0)
+    }
+
+    pub fn get_total_flushes(&self) -> u32 {
+        self.flushes.values().sum()
+    }
+
+    pub fn add_flush(&mut self, reason: FlushReason) {
+        *self.flushes.entry(reason).or_insert(0) += 1;
+    }
+
+    pub fn add(&mut self, other: &FrameStats) {
+        self.frames += other.frames;
+        for (reason, count) in &other.flushes {
+            *self.flushes.entry(*reason).or_insert(0) += count;
+        }
+        self.vertices += other.vertices;
+        self.indices += other.indices;
+        self.textures += other.textures;
+        self.draw_time += other.draw_time;
+        self.render_time += other.render_time;
+    }
+}
+
+// Adds up the stats of groups of frames
+pub struct StatsAggregator {
+    // 0 turns it off
+    frames_per_group: u32,
+    accumulator: FrameStats,
+    aggregated: Option<FrameStats>,
+}
+
+impl StatsAggregator {
+    pub fn new(frames_per_group: u32) -> Self {
+        Self {
+            frames_per_group,
+            accumulator: FrameStats::default(),
+            aggregated: None,
+        }
+    }
+
+    // Finishes the group once it has enough frames, a finished group that wasn't taken is replaced
+    pub fn add_frame(&mut self, stats: &FrameStats) {
+        if self.frames_per_group == 0 {
+            return;
+        }
+        self.accumulator.add(stats);
+        if self.accumulator.frames >= self.frames_per_group {
+            self.aggregated = Some(std::mem::take(&mut self.accumulator));
+        }
+    }
+
+    // The last finished group, each group is only returned once
+    pub fn take(&mut self) -> Option<FrameStats> {
+        self.aggregated.take()
+    }
+}
+
+// Prints the averages per frame
+impl fmt::Display for FrameStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let frames = self.frames.max(1);
+        let per_frame = |count: u64| count as f64 / frames as f64;
+        write!(
+            f,
+            "{} frames: {:.1} flushes ({:.1} state changes, {:.1} vertex capacity, {:.1} index capacity, {:.1} end of frame), {:.0} vertices, {:.0} indices, {:.1} textures, draw {:?}, render {:?} per frame",
+            self.frames,
+            per_frame(self.get_total_flushes() as u64),
+            per_frame(self.get_flushes(FlushReason::StateChange) as u64),
+            per_frame(self.get_flushes(FlushReason::VertexCapacity) as u64),
+            per_frame(self.get_flushes(FlushReason::IndexCapacity) as u64),
+            per_frame(self.get_flushes(FlushReason::EndOfFrame) as u64),
+            per_frame(self.vertices),
+            per_frame(self.indices),
+            per_frame(self.textures as u64),
+            self.draw_time / frames,
+            self.render_time / frames,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_frame(flushes: &[FlushReason], vertices: u64, draw_millis: u64) -> FrameStats {
+        let mut stats = FrameStats {
+            frames: 1,
+            vertices,
+            indices: vertices * 2,
+            textures: 2,
+            draw_time: Duration::from_millis(draw_millis),
+            ..FrameStats::default()
+        };
+        for reason in flushes {
+            stats.add_flush(*reason);
+        }
+        stats
+    }
+
+    #[test]
+    fn add_sums_everything() {
+        let mut stats = create_frame(&[FlushReason::StateChange, FlushReason::EndOfFrame], 10, 2);
+        stats.add(&create_frame(&[FlushReason::StateChange, FlushReason::VertexCapacity, FlushReason::EndOfFrame], 30, 4));
+        assert_eq!(stats.frames, 2);
+        assert_eq!(stats.get_flushes(FlushReason::StateChange), 2);
+        assert_eq!(stats.get_flushes(FlushReason::VertexCapacity), 1);
+        assert_eq!(stats.get_flushes(FlushReason::IndexCapacity), 0);
+        assert_eq!(stats.get_flushes(FlushReason::EndOfFrame), 2);
+        assert_eq!(stats.get_total_flushes(), 5);
+        assert_eq!((stats.vertices, stats.indices, stats.textures), (40, 80, 4));
+        assert_eq!(stats.draw_time, Duration::from_millis(6));
+    }
+
+    #[test]
+    fn aggregator_returns_each_group_once() {
+        let mut aggregator = StatsAggregator::new(2);
+        aggregator.add_frame(&create_frame(&[FlushReason::EndOfFrame], 10, 1));
+        assert!(aggregator.take().is_none());
+        aggregator.add_frame(&create_frame(&[FlushReason::EndOfFrame], 20, 1));
+        let group = aggregator.take().unwrap();
+        assert_eq!((group.frames, group.vertices), (2, 30));
+        assert!(aggregator.take().is_none());
+
+        // The next group starts empty
+        aggregator.add_frame(&create_frame(&[FlushReason::EndOfFrame], 5, 1));
+        aggregator.add_frame(&create_frame(&[FlushReason::EndOfFrame], 5, 1));
+        assert_eq!(aggregator.take().unwrap().vertices, 10);
+    }
+
+    #[test]
+    fn untaken_group_is_replaced() {
+        let mut aggregator = StatsAggregator::new(1);
+        aggregator.add_frame(&create_frame(&[], 1, 1));
+        aggregator.add_frame(&create_frame(&[], 2, 1));
+        assert_eq!(aggregator.take().unwrap().vertices, 2);
+    }
+
+    #[test]
+    fn disabled_aggregator_collects_nothing() {
+        let mut aggregator = StatsAggregator::new(0);
+        aggregator.add_frame(&create_frame(&[FlushReason::EndOfFrame], 10, 1));
+        assert!(aggregator.take().is_none());
+    }
+
+    #[test]
+    fn display_prints_averages() {
+        let mut stats = create_frame(&[FlushReason::EndOfFrame], 10, 2);
+        stats.add(&create_frame(&[FlushReason::EndOfFrame, FlushReason::StateChange], 30, 4));
+        let text = stats.to_string();
+        assert!(text.starts_with("2 frames: 1.5 flushes (0.5 state changes,"), "{}", text);
+        assert!(text.contains("20 vertices, 40 indices, 2.0 textures, draw 3ms"), "{}", text);
+    }
+}
@@ -0,0 +1,107 @@
+// Index data owned by a shape
+// Shapes start out with u16 indices and switch to u32 indices once an index doesn't fit into a u16 anymore
+#[derive(Clone, Debug)]
+pub enum Indices {
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+// Borrowed index data returned by Drawable::get_vertex_information
+#[derive(Copy, Clone, Debug)]
+pub enum IndexSlice<'a> {
+    U16(&'a [u16]),
+    U32(&'a [u32]),
+}
+
+impl Indices {
+    pub fn new() -> Self {
+        Indices::U16(vec![])
+    }
+
+    pub fn push(&mut self, index: u32) {
+        match self {
+            Indices::U16(indices) => {
+                if index > u16::MAX as u32 {
+                    let mut promoted: Vec<u32> = indices.iter().map(|i| *i as u32).collect();
+                    promoted.push(index);
+                    *self = Indices::U32(promoted);
+                } else {
+                    indices.push(index as u16);
+                }
+            },
+            Indices::U32(indices) => indices.push(index),
+        }
+    }
+
+    pub fn as_slice(&self) -> IndexSlice<'_> {
+        match self {
+            Indices::U16(indices) => IndexSlice::U16(indices),
+            Indices::U32(indices) => IndexSlice::U32(indices),
+        }
+    }
+}
+
+impl<'a> IndexSlice<'a> {
+    pub fn len(self) -> usize {
+        match self {
+            IndexSlice::U16(indices) => indices.len(),
+            IndexSlice::U32(indices) => indices.len(),
+        }
+    }
+
+    pub fn get(self, i: usize) -> u32 {
+        match self {
+            IndexSlice::U16(indices) => indices[i] as u32,
+            IndexSlice::U32(indices) => indices[i],
+        }
+    }
+
+    // Copies the indices while adding offset to each of them
+    pub fn to_offset_vec(self, offset: u32) -> Vec<u32> {
+        match self {
+            IndexSlice::U16(indices) => indices.iter().map(|i| *i as u32 + offset).collect(),
+            IndexSlice::U32(indices) => indices.iter().map(|i| *i + offset).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn small_indices_stay_u16() {
+        let mut indices = Indices::new();
+        for index in &[0, 1, u16::MAX as u32] {
+            indices.push(*index);
+        }
+        match indices {
+            Indices::U16(indices) => assert_eq!(indices, [0, 1, u16::MAX]),
+            Indices::U32(_) => panic!("indices were promoted"),
+        }
+    }
+
+    #[test]
+    fn large_index_promotes_to_u32() {
+        let mut indices = Indices::new();
+        indices.push(3);
+        indices.push(u16::MAX as u32 + 1);
+        // Stays u32 for small indices after that
+        indices.push(7);
+        match &indices {
+            Indices::U32(indices) => assert_eq!(indices, &[3, u16::MAX as u32 + 1, 7]),
+            Indices::U16(_) => panic!("indices were not promoted"),
+        }
+        let slice = indices.as_slice();
+        assert_eq!(slice.len(), 3);
+        assert_eq!(slice.get(1), u16::MAX as u32 + 1);
+    }
+
+    #[test]
+    fn offset_vec() {
+        let u16_indices = IndexSlice::U16(&[0, 2, u16::MAX]);
+        assert_eq!(u16_indices.to_offset_vec(10), [10, 12, u16::MAX as u32 + 10]);
+        let u32_indices = IndexSlice::U32(&[1, 100_000]);
+        assert_eq!(u32_indices.to_offset_vec(5), [6, 100_005]);
+    }
+}
@@ -0,0 +1,116 @@
+use cgmath::*;
+
+use super::camera::UsableTransform;
+use super::texture::TextureRegion;
+
+// One copy of a shape drawn with draw_instanced
+#[derive(Copy, Clone, Debug)]
+pub struct DrawInstance<'a> {
+    pub transform: UsableTransform,
+    // Multiplied with the color of the shape
+    pub tint: [f32; 4],
+    // Replaces the texture of a textured shape, colored shapes ignore it
+    pub texture_name: Option<&'a str>,
+}
+
+// The columns of a 3x3 matrix padded to vec4s like the camera transform in Uniforms
+pub fn padded_columns(matrix: &Matrix3<f32>) -> [[f32; 4]; 3] {
+    [
+        matrix.x.extend(0.0).into(),
+        matrix.y.extend(0.0).into(),
+        matrix.z.extend(0.0).into(),
+    ]
+}
+
+// Per-instance data read by shader.vs from the second vertex buffer
+// Batched shapes use an identity instance, meshes and instanced shapes get their transform from here
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct Instance {
+    // The padded columns of the 3x3 model matrix
+    pub transform: [[f32; 4]; 3],
+    pub tint: [f32; 4],
+    // The texture region of textured vertices, their tex coords have to be in 0..1
+    // A texture_layer of -1 keeps the layer and tex coords of the vertices
+    pub uv_offset: [f32; 2],
+    pub uv_scale: [f32; 2],
+    pub texture_layer: i32,
+}
+unsafe impl bytemuck::Pod for Instance {}
+unsafe impl bytemuck::Zeroable for Instance {}
+
+impl Instance {
+    pub fn new(transformation: Option<&UsableTransform>) -> Self {
+        let matrix = match transformation {
+            Some(transf) => transf.get_transformation_matrix(),
+            None => Matrix3::identity(),
+        };
+        Self {
+            transform: padded_columns(&matrix),
+            tint: [1.0, 1.0, 1.0, 1.0],
+            uv_offset: [0.0, 0.0],
+            uv_scale: [1.0, 1.0],
+            texture_layer: -1,
+        }
+    }
+
+    pub fn with_texture(transformation: &UsableTransform, tint: [f32; 4], region: Option<TextureRegion>) -> Self {
+        let mut instance = Self::new(Some(transformation));
+        instance.tint = tint;
+        if let Some(region) = region {
+            instance.set_texture_region(region);
+        }
+        instance
+    }
+
+    pub fn set_texture_region(&mut self, region: TextureRegion) {
+        self.uv_offset = region.uv_offset;
+        self.uv_scale = region.uv_scale;
+        self.texture_layer = region.layer as i32;
+    }
+
+    pub fn desc<'a>() -> wgpu::VertexBufferDescriptor<'a> {
+        use std::mem;
+        wgpu::VertexBufferDescriptor {
+            stride: mem::size_of::<Instance>() as wgpu::BufferAddress,
+            step_mode: wgpu::InputStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttributeDescriptor {
+                    offset: 0,
+                    shader_location: 5,
+                    format: wgpu::VertexFormat::Float4,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 6,
+                    format: wgpu::VertexFormat::Float4,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: (2*mem::size_of::<[f32; 4]>()) as wgpu::BufferAddress,
+                    shader_location: 7,
+                    format: wgpu::VertexFormat::Float4,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: (3*mem::size_of::<[f32; 4]>()) as wgpu::BufferAddress,
+                    shader_location: 8,
+                    format: wgpu::VertexFormat::Float4,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: (4*mem::size_of::<[f32; 4]>()) as wgpu::BufferAddress,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Float2,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: (4*mem::size_of::<[f32; 4]>() + mem::size_of::<[f32; 2]>()) as wgpu::BufferAddress,
+                    shader_location: 10,
+                    format: wgpu::VertexFormat::Float2,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: (4*mem::size_of::<[f32; 4]>() + 2*mem::size_of::<[f32; 2]>()) as wgpu::BufferAddress,
+                    shader_location: 11,
+                    format: wgpu::VertexFormat::Int,
+                },
+            ],
+        }
+    }
+}
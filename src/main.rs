@@ -5,11 +5,26 @@ use winit::{
 
 use cgmath::*;
 
+use std::collections::HashMap;
+
 mod texture;
 // use texture::Texture;
+use texture::Sampler;
+mod texture_atlas;
+
+mod blend_mode;
+use blend_mode::BlendMode;
+
+mod clip;
+use clip::ScissorRect;
 
 mod vertex;
 
+mod instance;
+use instance::DrawInstance;
+
+mod indices;
+
 mod point;
 use point::Point;
 
@@ -25,74 +40,463 @@ mod window;
 mod renderer;
 use renderer::*;
 
+mod mesh_store;
+use mesh_store::MeshHandle;
+
+mod offscreen;
+
+mod viewport;
+use viewport::Viewport;
+
+mod post_processing;
+use post_processing::PostEffect;
+
+mod frame_stats;
+
+mod staging;
+
+mod software_renderer;
+use software_renderer::SoftwareRenderer;
+
 mod objects;
 use objects::polygons::*;
 use objects::lines::*;
 use objects::bezier_curves::*;
 
+// The sampler is used by every shape drawn with the texture unless set_sampler overrides it
+const TEXTURES: [(&[u8], &str, Sampler); 2] = [
+    (include_bytes!("textures/awesomeface_with_transparency.png"), "awesomeface.png", Sampler::ClampLinear),
+    (include_bytes!("textures/happy-tree.png"), "happy-tree.png", Sampler::ClampNearest),
+];
+
+// The scene is rendered into this target before every frame and shown in a corner of the frame
+// It has the frame's 4:3 aspect ratio so the camera doesn't have to change
+const MINIMAP_TARGET: &str = "minimap";
+const MINIMAP_SIZE: (u32, u32) = (256, 192);
+
+// Both renderers get the same layer size, so they accept the same render targets
+const TEXTURE_LAYER_SIZE: u32 = 512;
+
+// Setting this to a directory like src/shaders loads the shaders from there instead of the ones built into the binary
+// The shaders are reloaded when they change
+const SHADER_DIRECTORY_VARIABLE: &str = "RENDER_ENGINE_SHADERS";
+
+// Setting this to a number of frames prints the renderer's stats averaged over that many frames
+const STATS_VARIABLE: &str = "RENDER_ENGINE_STATS";
+
+// Shapes drawn with the dissolve material lose blocks of pixels, more of them the higher the threshold is
+fn register_materials(renderer: &mut Renderer, shader_directory: &Option<String>, dissolve_threshold: f32) {
+    let dissolve_shader = match shader_directory {
+        Some(directory) => ShaderSource::File(format!("{}/dissolve.fs", directory)),
+        None => ShaderSource::Spirv(include_bytes!(concat!(env!("OUT_DIR"), "/dissolve.fs.spv"))),
+    };
+    let result = renderer.register_material("dissolve", &MaterialDescriptor {
+        fragment_shader: dissolve_shader,
+        vertex_shader: None,
+        // The uniform block is padded to 16 bytes
+        uniform_data: Some(bytemuck::cast_slice(&[dissolve_threshold, 0.0, 0.0, 0.0])),
+    });
+    // Draws fall back to the default material
+    if let Err(error) = result {
+        eprintln!("{}", error);
+    }
+}
+
+struct Scene {
+    t0: Triangle,
+    r1: Rectangle,
+    // The face never changes, so it stays on the gpu
+    face_mesh: MeshHandle,
+    // A row of tinted tiles drawn with one draw call
+    tile: Rectangle,
+    tiles: Vec<DrawInstance<'static>>,
+    // Shows the minimap target, including the minimap of the frame before
+    minimap: Rectangle,
+    poly: Polygon,
+    l0: Line,
+    l1: LineStrip,
+    b0: BezierCurve,
+    b1: BezierCurves3rdDeg,
+}
+
+impl Scene {
+    // Textures have to be loaded before, meshes look them up when they are created
+    fn new<R: ShapeRenderer>(renderer: &mut R) -> Self {
+        let t0 = Triangle::new(Point{x:-0.3, y:-0.3}, Point{x: 0.3, y:-0.3}, Point{x: 0.0, y: 0.3}, None, Some(&[0.1,0.0,0.8,0.5]));
+        let r1 = Rectangle::new(Point{x:-0.5, y: 0.5}, Point{x: 0.5, y:-0.5}, Some(String::from("happy-tree.png")), None);
+        let r2 = Rectangle::new(Point{x:-0.5, y: 0.5}, Point{x: 0.5, y:-0.5}, Some(String::from("awesomeface.png")), None);
+        let face_mesh = renderer.create_mesh(&r2);
+        let tile = Rectangle::new(Point{x:-0.05, y: 0.05}, Point{x: 0.05, y:-0.05}, Some(String::from("happy-tree.png")), None);
+        let tiles = (0..8).map(|i| DrawInstance {
+            transform: UsableTransform{
+                translation: vec2(-0.9 + 0.12*i as f32, -0.65),
+                scale: vec2(1.0,1.0),
+                rotation: 0.2*i as f32,
+            },
+            tint: [1.0, 1.0 - 0.1*i as f32, 0.3 + 0.1*i as f32, 1.0],
+            // Every other tile shows the face instead of the tree
+            texture_name: if i % 2 == 0 { None } else { Some("awesomeface.png") },
+        }).collect();
+        let minimap = Rectangle::new(Point{x: 0.56, y: 0.72}, Point{x: 0.96, y: 0.42}, Some(String::from(MINIMAP_TARGET)), None);
+        let poly = Polygon::new(
+            &[
+                Point{x: 0.00, y: 1.00},
+                Point{x:-0.24, y: 0.31},
+                Point{x:-1.00, y: 0.31},
+                Point{x:-0.38, y:-0.10},
+                Point{x:-0.62, y:-0.79},
+                Point{x: 0.00, y:-0.36},
+
+                Point{x: 0.62, y:-0.79},
+                Point{x: 0.38, y:-0.10},
+                Point{x: 1.00, y: 0.31},
+                Point{x: 0.24, y: 0.31},
+            ],
+            Some(String::from("happy-tree.png")),
+            None,
+        );
+        let l0 = Line::new(Point{x:-0.3, y:0.3}, Point{x:0.3, y:-0.3}, 0.01, Some(String::from("happy-tree.png")), None);
+
+        let bezier_points = [Point{x:-0.3, y:-0.3}, Point{x: 0.0, y: 0.3}, Point{x: 0.5, y:-0.3}, Point{x:0.15, y:-0.5}, Point{x:-0.2, y:-0.3}, Point{x: 0.2, y:0.2}, Point{x: 0.4, y:0.1}];
+        let l1 = LineStrip::new(
+            &bezier_points,
+            0.01, None, Some(&[0.0,0.0,0.0,1.0])
+        );
+        let b0 = BezierCurve::new(
+            &bezier_points,
+            100, 0.01, None, Some(&[0.0,1.0,1.0,1.0])
+        );
+        let b1 = BezierCurves3rdDeg::automatic_control_points(
+            &bezier_points,
+            true,
+            100, 0.01, None, Some(&[1.0,0.0,0.0,1.0])
+        );
+
+        Self {
+            t0,
+            r1,
+            face_mesh,
+            tile,
+            tiles,
+            minimap,
+            poly,
+            l0,
+            l1,
+            b0,
+            b1,
+        }
+    }
+
+    // size is the size in pixels of the frame, target or viewport the scene is drawn into
+    fn draw<R: ShapeRenderer>(&self, renderer: &mut R, size: (u32, u32)) {
+        // The curves are drawn first but stay on top of everything else
+        renderer.set_layer(1);
+        let bezier_transform = UsableTransform{
+            translation: vec2(-0.6,0.6),
+            scale: vec2(1.0,1.0),
+            rotation: 0.0,
+        };
+        renderer.draw(&self.l1, Some(&bezier_transform));
+        // renderer.draw(self.b0.get_line_strip(), Some(&bezier_transform));
+        self.b0.draw(renderer, Some(&bezier_transform));
+        self.b1.draw(renderer, Some(&bezier_transform));
+        renderer.draw(&self.minimap, None);
+        renderer.set_layer(0);
+
+        // The star is scaled down, so it looks better filtered than with the tree's nearest sampler
+        renderer.set_sampler(Some(Sampler::ClampLinear));
+        renderer.set_material(Some("dissolve"));
+        renderer.draw(&self.poly,
+            Some(&UsableTransform{
+                translation: vec2(-0.5,-0.5),
+                scale: vec2(0.5,0.5),
+                rotation: 0.0,
+            })
+        );
+        renderer.set_material(None);
+        renderer.set_sampler(None);
+        // Only the top half shows the tree, scissor rectangles are relative to the viewport if there is one
+        renderer.push_scissor_rect(ScissorRect{x: 0, y: 0, width: size.0, height: size.1 / 2});
+        renderer.draw(&self.r1,
+            Some(&UsableTransform{
+                translation: vec2(0.5,0.0),
+                scale: vec2(1.0,0.5),
+                rotation: 0.7814,
+            })
+        );
+        if let Err(error) = renderer.pop_clip() {
+            eprintln!("{}", error);
+        }
+        // The face is cut out by a star
+        let face_transform = UsableTransform{
+            translation: vec2(1.0,-0.5),
+            scale: vec2(1.0,1.0),
+            rotation: 0.0,
+        };
+        renderer.push_clip_mask(&self.poly, Some(&UsableTransform{
+            translation: vec2(1.0,-0.5),
+            scale: vec2(0.5,0.5),
+            rotation: 0.0,
+        }));
+        renderer.draw_mesh(self.face_mesh, Some(&face_transform), None);
+        if let Err(error) = renderer.pop_clip() {
+            eprintln!("{}", error);
+        }
+        renderer.draw_instanced(&self.tile, &self.tiles);
+        // The triangle is a translucent glow on top of the rectangles
+        renderer.set_blend_mode(BlendMode::Additive);
+        renderer.draw(&self.t0, None);
+        renderer.set_blend_mode(BlendMode::Normal);
+        renderer.draw(&self.l0, None);
+    }
+}
+
+// Renders a single frame of the scene without opening a window and saves it as a png
+fn render_headless(output_path: &str, width: u32, height: u32) -> Result<(), RendererError> {
+    use futures::executor::block_on;
+
+    let mut renderer = block_on(Renderer::new(
+        &RendererDescriptor {
+            initial_vertices: 10000,
+            initial_indices: 15000,
+            max_vertices: None,
+            max_indices: None,
+            index_format: wgpu::IndexFormat::Uint16,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            texture_layer_size: TEXTURE_LAYER_SIZE,
+            // Matches the software renderer, which doesn't anti-alias
+            sample_count: 1,
+            shader_directory: None,
+            watch_shaders: false,
+        },
+        None,
+    ))?;
+
+    // Shapes using textures that failed to load are drawn with the placeholder
+    for (bytes, label, sampler) in &TEXTURES {
+        if let Err(error) = renderer.load_texture(bytes, String::from(*label)) {
+            eprintln!("Could not load {}: {}", label, error);
+        }
+        renderer.set_texture_sampler(label, *sampler);
+    }
+    register_materials(&mut renderer, &None, 0.3);
+    renderer.create_render_target(MINIMAP_TARGET, MINIMAP_SIZE.0, MINIMAP_SIZE.1)?;
+    renderer.set_texture_sampler(MINIMAP_TARGET, Sampler::ClampLinear);
+
+    let scene = Scene::new(&mut renderer);
+
+    let mut camera = Camera::new();
+    camera.aspect_ratio = width as f32 / height as f32;
+    renderer.update(&camera);
+
+    renderer.render_to_target(MINIMAP_TARGET, |renderer| scene.draw(renderer, MINIMAP_SIZE))?;
+    let image = renderer.render_offscreen(width, height, |renderer| scene.draw(renderer, (width, height)))?;
+    save_image(&image, output_path);
+    Ok(())
+}
+
+// Same as render_headless but rasterizes on the cpu so no gpu is needed
+fn render_software(output_path: &str, width: u32, height: u32) {
+    let mut renderer = SoftwareRenderer::new(width, height, TEXTURE_LAYER_SIZE);
+
+    for (bytes, label, sampler) in &TEXTURES {
+        if let Err(error) = renderer.load_texture(bytes, String::from(*label)) {
+            eprintln!("Could not load {}: {}", label, error);
+        }
+        renderer.set_texture_sampler(label, *sampler);
+    }
+    if let Err(error) = renderer.create_render_target(MINIMAP_TARGET, MINIMAP_SIZE.0, MINIMAP_SIZE.1) {
+        eprintln!("{}", error);
+    }
+    renderer.set_texture_sampler(MINIMAP_TARGET, Sampler::ClampLinear);
+
+    let scene = Scene::new(&mut renderer);
+
+    let mut camera = Camera::new();
+    camera.aspect_ratio = width as f32 / height as f32;
+    renderer.update(&camera);
+
+    if let Err(error) = renderer.render_to_target(MINIMAP_TARGET, |renderer| scene.draw(renderer, MINIMAP_SIZE)) {
+        eprintln!("{}", error);
+    }
+    let image = renderer.render(|renderer| scene.draw(renderer, (width, height)));
+    save_image(&image, output_path);
+}
+
+// Exits with an error code so scripts comparing the images notice
+fn save_image(image: &image::RgbaImage, output_path: &str) {
+    if let Err(error) = image.save(output_path) {
+        eprintln!("Could not save {}: {}", output_path, error);
+        std::process::exit(1);
+    }
+}
+
+// A window showing the scene through a camera of its own
+// A split window shows its camera on the left half and an overview of the whole scene on the right half
+struct SceneWindow {
+    window: window::Window,
+    camera: Camera,
+    camera_controller: CameraController,
+    split: bool,
+}
+
+impl SceneWindow {
+    fn new(window: window::Window, split: bool) -> Self {
+        let mut scene_window = Self {
+            window,
+            camera: Camera::new(),
+            camera_controller: CameraController::new(0.04, 1.04, 0.06),
+            split,
+        };
+        scene_window.camera.aspect_ratio = scene_window.get_camera_viewport().get_aspect_ratio();
+        scene_window
+    }
+
+    fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>, device: &wgpu::Device) {
+        self.window.resize(new_size, device);
+        self.camera.aspect_ratio = self.get_camera_viewport().get_aspect_ratio();
+    }
+
+    fn get_camera_viewport(&self) -> Viewport {
+        let (width, height) = self.window.get_size();
+        let width = if self.split { width / 2 } else { width };
+        Viewport { x: 0, y: 0, width: width.max(1), height: height.max(1) }
+    }
+
+    // None if the window isn't split
+    fn get_overview_viewport(&self) -> Option<Viewport> {
+        if !self.split {
+            return None;
+        }
+        let camera_viewport = self.get_camera_viewport();
+        let (width, height) = self.window.get_size();
+        Some(Viewport {
+            x: camera_viewport.width,
+            y: 0,
+            width: width.saturating_sub(camera_viewport.width).max(1),
+            height: height.max(1),
+        })
+    }
+
+    // Both halves of a split window are drawn within the same frame
+    fn draw<R: ShapeRenderer>(&self, scene: &Scene, renderer: &mut R) {
+        let overview_viewport = match self.get_overview_viewport() {
+            Some(viewport) => viewport,
+            None => return scene.draw(renderer, self.window.get_size()),
+        };
+        let camera_viewport = self.get_camera_viewport();
+        renderer.set_viewport(Some(camera_viewport));
+        renderer.update(&self.camera);
+        scene.draw(renderer, (camera_viewport.width, camera_viewport.height));
+
+        let mut overview_camera = Camera::new();
+        overview_camera.aspect_ratio = overview_viewport.get_aspect_ratio();
+        renderer.set_viewport(Some(overview_viewport));
+        renderer.update(&overview_camera);
+        scene.draw(renderer, (overview_viewport.width, overview_viewport.height));
+    }
+}
+
 fn main() {
+    // Usage: render-engine [--headless | --software] <output.png>
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(i) = args.iter().position(|arg| arg == "--headless") {
+        let output_path = args.get(i+1).map(String::as_str).unwrap_or("output.png");
+        if let Err(error) = render_headless(output_path, 800, 600) {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(i) = args.iter().position(|arg| arg == "--software") {
+        let output_path = args.get(i+1).map(String::as_str).unwrap_or("output.png");
+        render_software(output_path, 800, 600);
+        return;
+    }
+
     let event_loop = EventLoop::new();
     let format = wgpu::TextureFormat::Bgra8UnormSrgb;
     
     use futures::executor::block_on;
     
-    let mut window = window::Window::new(&event_loop, format);
+    let main_window = window::Window::new(&event_loop, "render-engine", format);
+    let inspector_window = window::Window::new(&event_loop, "render-engine inspector", format);
 
-    let mut renderer = block_on(Renderer::new(10000, 15000, format, Some(window.get_surface())));
+    let shader_directory = std::env::var(SHADER_DIRECTORY_VARIABLE).ok();
 
-    window.init_swapchain(renderer.get_device());
+    let renderer = block_on(Renderer::new(
+        &RendererDescriptor {
+            initial_vertices: 10000,
+            initial_indices: 15000,
+            max_vertices: None,
+            max_indices: None,
+            index_format: wgpu::IndexFormat::Uint16,
+            format,
+            texture_layer_size: TEXTURE_LAYER_SIZE,
+            sample_count: 4,
+            shader_directory: shader_directory.clone(),
+            // Shader edits show up without restarting
+            watch_shaders: shader_directory.is_some(),
+        },
+        Some(main_window.get_surface()),
+    ));
+    let mut renderer = match renderer {
+        Ok(renderer) => renderer,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        },
+    };
 
-    let mut camera = Camera::new();
+    // Closing the main window quits, inspectors can be closed on their own
+    let main_window_id = main_window.winit_window.id();
+    let mut windows = HashMap::new();
+    for (mut window, split) in [(main_window, false), (inspector_window, true)] {
+        window.init_swapchain(renderer.get_device());
+        windows.insert(window.winit_window.id(), SceneWindow::new(window, split));
+    }
 
-    let mut camera_controller = CameraController::new(0.04, 1.04, 0.06);
-
-    renderer.load_texture(include_bytes!("textures/awesomeface_with_transparency.png"), String::from("awesomeface.png"));
-    renderer.load_texture(include_bytes!("textures/happy-tree.png"), String::from("happy-tree.png"));
-
-    let t0 = Triangle::new(Point{x:-0.3, y:-0.3}, Point{x: 0.3, y:-0.3}, Point{x: 0.0, y: 0.3}, None, Some(&[0.1,0.0,0.8,0.5]));
-    let r1 = Rectangle::new(Point{x:-0.5, y: 0.5}, Point{x: 0.5, y:-0.5}, Some(String::from("happy-tree.png")), None);
-    let r2 = Rectangle::new(Point{x:-0.5, y: 0.5}, Point{x: 0.5, y:-0.5}, Some(String::from("awesomeface.png")), None);
-    let poly = Polygon::new(
-        &[
-            Point{x: 0.00, y: 1.00},
-            Point{x:-0.24, y: 0.31},
-            Point{x:-1.00, y: 0.31},
-            Point{x:-0.38, y:-0.10},
-            Point{x:-0.62, y:-0.79},
-            Point{x: 0.00, y:-0.36},
-
-            Point{x: 0.62, y:-0.79},
-            Point{x: 0.38, y:-0.10},
-            Point{x: 1.00, y: 0.31},
-            Point{x: 0.24, y: 0.31},
-        ],
-        Some(String::from("happy-tree.png")),
-        None,
-    );
-    let l0 = Line::new(Point{x:-0.3, y:0.3}, Point{x:0.3, y:-0.3}, 0.01, Some(String::from("happy-tree.png")), None);
-
-    let bezier_points = [Point{x:-0.3, y:-0.3}, Point{x: 0.0, y: 0.3}, Point{x: 0.5, y:-0.3}, Point{x:0.15, y:-0.5}, Point{x:-0.2, y:-0.3}, Point{x: 0.2, y:0.2}, Point{x: 0.4, y:0.1}];
-    let l1 = LineStrip::new(
-        &bezier_points,
-        0.01, None, Some(&[0.0,0.0,0.0,1.0])
-    );
-    let b0 = BezierCurve::new(
-        &bezier_points,
-        100, 0.01, None, Some(&[0.0,1.0,1.0,1.0])
-    );
-    let b1 = BezierCurves3rdDeg::automatic_control_points(
-        &bezier_points,
-        true,
-        100, 0.01, None, Some(&[1.0,0.0,0.0,1.0])
-    );
+    for (bytes, label, sampler) in &TEXTURES {
+        if let Err(error) = renderer.load_atlas_texture(bytes, String::from(*label)) {
+            eprintln!("Could not load {}: {}", label, error);
+        }
+        renderer.set_texture_sampler(label, *sampler);
+    }
+    register_materials(&mut renderer, &shader_directory, 0.0);
+    // The frame just doesn't show a minimap if this fails
+    if let Err(error) = renderer.create_render_target(MINIMAP_TARGET, MINIMAP_SIZE.0, MINIMAP_SIZE.1) {
+        eprintln!("{}", error);
+    }
+    renderer.set_texture_sampler(MINIMAP_TARGET, Sampler::ClampLinear);
+    // Only the window is post processed so the headless output still matches the software renderer
+    let post_effects = [
+        PostEffect::Bloom { threshold: 0.8, radius: 6.0, intensity: 0.6 },
+        PostEffect::Vignette { strength: 0.4 },
+    ];
+    if let Err(error) = renderer.set_post_effects(&post_effects) {
+        eprintln!("{}", error);
+    }
+    if let Some(frames) = std::env::var(STATS_VARIABLE).ok().and_then(|frames| frames.parse().ok()) {
+        renderer.set_stats_aggregation(frames);
+    }
+
+    let scene = Scene::new(&mut renderer);
+    let start_time = std::time::Instant::now();
 
     event_loop.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::Poll;
         match event {
-            Event::WindowEvent { ref event, window_id } if window_id == window.winit_window.id() => {
-                if !camera_controller.process_events(&event) {
+            Event::WindowEvent { ref event, window_id } => {
+                let scene_window = match windows.get_mut(&window_id) {
+                    Some(scene_window) => scene_window,
+                    None => return,
+                };
+                if !scene_window.camera_controller.process_events(&event) {
                     match event {
-                        WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+                        WindowEvent::CloseRequested if window_id == main_window_id => *control_flow = ControlFlow::Exit,
+                        WindowEvent::CloseRequested => {
+                            windows.remove(&window_id);
+                        },
                         WindowEvent::KeyboardInput { input, .. } => {
                             match input {
                                 KeyboardInput {
@@ -104,59 +508,65 @@ fn main() {
                             }
                         },
                         WindowEvent::Resized(physical_size) => {
-                            window.resize(*physical_size, renderer.get_device());
-                            camera.aspect_ratio = window.get_aspect_ratio();
+                            scene_window.resize(*physical_size, renderer.get_device());
                         }
                         WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                            window.resize(**new_inner_size, renderer.get_device());
-                            camera.aspect_ratio = window.get_aspect_ratio();
+                            scene_window.resize(**new_inner_size, renderer.get_device());
                         }
                         _ => {},
                     }
                 }
             }
-            Event::RedrawRequested(_) => {
-                let frame = DrawableFrame::from_sc_output(window.get_next_frame().unwrap());
-                camera_controller.update_camera(&mut camera);
-                renderer.update(&camera);
-                
-                renderer.begin_render(frame);
-                renderer.draw(&poly,
-                    Some(&UsableTransform{
-                        translation: vec2(-0.5,-0.5),
-                        scale: vec2(0.5,0.5),
-                        rotation: 0.0,
-                    })
-                );
-                renderer.draw(&r1, 
-                    Some(&UsableTransform{
-                        translation: vec2(0.5,0.0),
-                        scale: vec2(1.0,0.5),
-                        rotation: 0.7814,
-                    })
-                );
-                renderer.draw(&r2, 
-                    Some(&UsableTransform{
-                        translation: vec2(1.0,-0.5),
-                        scale: vec2(1.0,1.0),
-                        rotation: 0.0,
-                    })
-                );
-                renderer.draw(&t0, None);
-                renderer.draw(&l0, None);
-                let bezier_transform = UsableTransform{
-                    translation: vec2(-0.6,0.6),
-                    scale: vec2(1.0,1.0),
-                    rotation: 0.0,
+            Event::RedrawRequested(window_id) => {
+                let scene_window = match windows.get_mut(&window_id) {
+                    Some(scene_window) => scene_window,
+                    None => return,
+                };
+                let (width, height) = scene_window.window.get_size();
+                // The frame is skipped, the next redraw tries again
+                let sc_output = match scene_window.window.get_next_frame() {
+                    Ok(sc_output) => sc_output,
+                    Err(error) => {
+                        eprintln!("{}", error);
+                        return;
+                    },
                 };
-                renderer.draw(&l1, Some(&bezier_transform));
-                // renderer.draw(b0.get_line_strip(), Some(&bezier_transform));
-                b0.draw(&mut renderer, Some(&bezier_transform));
-                b1.draw(&mut renderer, Some(&bezier_transform));
-                renderer.end_render();
+                let frame = DrawableFrame::from_sc_output(sc_output, width, height);
+                let camera_controller = &scene_window.camera_controller;
+                camera_controller.update_camera(&mut scene_window.camera);
+                renderer.update(&scene_window.camera);
+                // The star dissolves and comes back every few seconds
+                // This only fails if the material couldn't be registered, which has already been logged
+                let dissolve_threshold = (1.0 - start_time.elapsed().as_secs_f32().cos()) / 2.0;
+                let _ = renderer.set_material_uniforms("dissolve", bytemuck::cast_slice(&[dissolve_threshold, 0.0, 0.0, 0.0]));
+
+                // The target has to be finished before the frame that shows it begins
+                // Every window shows the same minimap, so it is only rendered along with the main window
+                if window_id == main_window_id {
+                    if let Err(error) = renderer.render_to_target(MINIMAP_TARGET, |renderer| scene.draw(renderer, MINIMAP_SIZE)) {
+                        eprintln!("{}", error);
+                    }
+                }
+                let result = renderer.begin_render(frame).and_then(|_| {
+                    scene_window.draw(&scene, &mut renderer);
+                    renderer.end_render()
+                });
+                if let Err(error) = result {
+                    eprintln!("{}", error);
+                }
+                for error in renderer.take_shader_errors() {
+                    eprintln!("{}", error);
+                }
+                if window_id == main_window_id {
+                    if let Some(stats) = renderer.take_aggregated_stats() {
+                        println!("{}", stats);
+                    }
+                }
             }
             Event::MainEventsCleared => {
-                window.winit_window.request_redraw();
+                for scene_window in windows.values() {
+                    scene_window.window.winit_window.request_redraw();
+                }
             }
             _ => {},
         }
@@ -0,0 +1,104 @@
+// Identifies a mesh created with create_mesh, only valid for the renderer that created it
+// The generation tells a removed mesh apart from the mesh that reused its slot
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MeshHandle {
+    id: usize,
+    generation: u32,
+}
+
+struct MeshSlot<T> {
+    generation: u32,
+    mesh: Option<T>,
+}
+
+// The meshes of a renderer by handle
+// Slots of removed meshes are reused, handles of the removed meshes don't find anything in them anymore
+pub struct MeshStore<T> {
+    slots: Vec<MeshSlot<T>>,
+}
+
+impl<T> MeshStore<T> {
+    pub fn new() -> Self {
+        Self {
+            slots: vec![],
+        }
+    }
+
+    pub fn insert(&mut self, mesh: T) -> MeshHandle {
+        match self.slots.iter().position(|slot| slot.mesh.is_none()) {
+            Some(id) => {
+                let slot = &mut self.slots[id];
+                slot.mesh = Some(mesh);
+                MeshHandle { id, generation: slot.generation }
+            },
+            None => {
+                self.slots.push(MeshSlot { generation: 0, mesh: Some(mesh) });
+                MeshHandle { id: self.slots.len() - 1, generation: 0 }
+            },
+        }
+    }
+
+    // None if the mesh was removed or the handle is from another renderer
+    pub fn get(&self, handle: MeshHandle) -> Option<&T> {
+        match self.slots.get(handle.id) {
+            Some(slot) if slot.generation == handle.generation => slot.mesh.as_ref(),
+            _ => None,
+        }
+    }
+
+    // Returns the removed mesh, None if there was no mesh for the handle
+    pub fn remove(&mut self, handle: MeshHandle) -> Option<T> {
+        match self.slots.get_mut(handle.id) {
+            Some(slot) if slot.generation == handle.generation && slot.mesh.is_some() => {
+                slot.generation = slot.generation.wrapping_add(1);
+                slot.mesh.take()
+            },
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_and_get() {
+        let mut store = MeshStore::new();
+        let a = store.insert("a");
+        let b = store.insert("b");
+        assert_ne!(a, b);
+        assert_eq!(store.get(a), Some(&"a"));
+        assert_eq!(store.get(b), Some(&"b"));
+    }
+
+    #[test]
+    fn removed_handle_is_stale() {
+        let mut store = MeshStore::new();
+        let a = store.insert("a");
+        assert_eq!(store.remove(a), Some("a"));
+        assert_eq!(store.get(a), None);
+        assert_eq!(store.remove(a), None);
+    }
+
+    #[test]
+    fn reused_slot_keeps_stale_handle_out() {
+        let mut store = MeshStore::new();
+        let a = store.insert("a");
+        store.remove(a);
+        let b = store.insert("b");
+        // b got the slot of a
+        assert_eq!(b.id, a.id);
+        assert_eq!(store.get(a), None);
+        assert_eq!(store.remove(a), None);
+        assert_eq!(store.get(b), Some(&"b"));
+    }
+
+    #[test]
+    fn unknown_handle() {
+        let mut store: MeshStore<&str> = MeshStore::new();
+        let other_store_handle = MeshStore::new().insert("a");
+        assert_eq!(store.get(other_store_handle), None);
+        assert_eq!(store.remove(other_store_handle), None);
+    }
+}
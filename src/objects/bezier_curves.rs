@@ -73,7 +73,7 @@ impl BezierCurve {
         &self.line_strip
     }
     
-    pub fn draw(&self, renderer: &mut Renderer, transformation: Option<&UsableTransform>) {
+    pub fn draw<R: ShapeRenderer>(&self, renderer: &mut R, transformation: Option<&UsableTransform>) {
         renderer.draw(&self.line_strip, transformation);
     }
 }
@@ -191,7 +191,7 @@ impl BezierCurves3rdDeg {
         }
         bz3d
     }
-    pub fn draw(&self, renderer: &mut Renderer, transformation: Option<&UsableTransform>) {
+    pub fn draw<R: ShapeRenderer>(&self, renderer: &mut R, transformation: Option<&UsableTransform>) {
         for bz_curve in &self.bezier_curves {
             bz_curve.draw(renderer, transformation);
         }
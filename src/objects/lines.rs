@@ -1,6 +1,7 @@
 use super::super::vertex::*;
 use super::super::point::*;
 use super::super::renderer;
+use super::super::indices::*;
 
 use super::helper_functions::gen_tex_coords;
 
@@ -51,10 +52,10 @@ impl Line {
         norm *= width/2.0;
 
         let mut vertices = [
-            Vertex { position: p0, texture_binding:-1, tex_coords_or_color: [1.0, 1.0, 0.0, 1.0] },
-            Vertex { position: p0, texture_binding:-1, tex_coords_or_color: [1.0, 1.0, 0.0, 1.0] },
-            Vertex { position: p1, texture_binding:-1, tex_coords_or_color: [1.0, 1.0, 0.0, 1.0] },
-            Vertex { position: p1, texture_binding:-1, tex_coords_or_color: [1.0, 1.0, 0.0, 1.0] },
+            Vertex { position: p0, texture_binding:-1, tex_coords_or_color: [1.0, 1.0, 0.0, 1.0], sampler: DEFAULT_SAMPLER, transform_id: IDENTITY_TRANSFORM_ID },
+            Vertex { position: p0, texture_binding:-1, tex_coords_or_color: [1.0, 1.0, 0.0, 1.0], sampler: DEFAULT_SAMPLER, transform_id: IDENTITY_TRANSFORM_ID },
+            Vertex { position: p1, texture_binding:-1, tex_coords_or_color: [1.0, 1.0, 0.0, 1.0], sampler: DEFAULT_SAMPLER, transform_id: IDENTITY_TRANSFORM_ID },
+            Vertex { position: p1, texture_binding:-1, tex_coords_or_color: [1.0, 1.0, 0.0, 1.0], sampler: DEFAULT_SAMPLER, transform_id: IDENTITY_TRANSFORM_ID },
         ];
 
         vertices[0].position -= norm;
@@ -80,8 +81,8 @@ impl Line {
 }
 
 impl<'a> renderer::Drawable<'a> for Line {
-    fn get_vertex_information(&'a self) -> (&'a[u16], &'a[Vertex]) {
-        (&self.indices, &self.vertices)
+    fn get_vertex_information(&'a self) -> (IndexSlice<'a>, &'a[Vertex]) {
+        (IndexSlice::U16(&self.indices), &self.vertices)
     }
 
     fn get_texture_name(&self) -> Option<String> {
@@ -93,7 +94,7 @@ impl<'a> renderer::Drawable<'a> for Line {
 }
 
 pub struct LineStrip {
-    indices: Vec<u16>,
+    indices: Indices,
     vertices: Vec<Vertex>,
     texture: Option<String>,
 }
@@ -107,10 +108,10 @@ impl LineStrip {
         norm.normalize();
         norm *= width/2.0;
         let vertices_starting_index = self.vertices.len();
-        self.vertices.push(Vertex{position: prev_point-norm, texture_binding: 0, tex_coords_or_color: [0.0,0.0,0.0,0.0]});
-        self.vertices.push(Vertex{position: prev_point+norm, texture_binding: 0, tex_coords_or_color: [0.0,0.0,0.0,0.0]});
-        self.vertices.push(Vertex{position:      point+norm, texture_binding: 0, tex_coords_or_color: [0.0,0.0,0.0,0.0]});
-        self.vertices.push(Vertex{position:      point-norm, texture_binding: 0, tex_coords_or_color: [0.0,0.0,0.0,0.0]});
+        self.vertices.push(Vertex{position: prev_point-norm, texture_binding: 0, tex_coords_or_color: [0.0,0.0,0.0,0.0], sampler: DEFAULT_SAMPLER, transform_id: IDENTITY_TRANSFORM_ID});
+        self.vertices.push(Vertex{position: prev_point+norm, texture_binding: 0, tex_coords_or_color: [0.0,0.0,0.0,0.0], sampler: DEFAULT_SAMPLER, transform_id: IDENTITY_TRANSFORM_ID});
+        self.vertices.push(Vertex{position:      point+norm, texture_binding: 0, tex_coords_or_color: [0.0,0.0,0.0,0.0], sampler: DEFAULT_SAMPLER, transform_id: IDENTITY_TRANSFORM_ID});
+        self.vertices.push(Vertex{position:      point-norm, texture_binding: 0, tex_coords_or_color: [0.0,0.0,0.0,0.0], sampler: DEFAULT_SAMPLER, transform_id: IDENTITY_TRANSFORM_ID});
 
         match color {
             Some(col) => {
@@ -126,27 +127,27 @@ impl LineStrip {
         }
         
         // Add the new quad to indices
-        self.indices.push(vertices_starting_index as u16 +0);
-        self.indices.push(vertices_starting_index as u16 +1);
-        self.indices.push(vertices_starting_index as u16 +2);
-        self.indices.push(vertices_starting_index as u16 +2);
-        self.indices.push(vertices_starting_index as u16 +3);
-        self.indices.push(vertices_starting_index as u16 +0);
+        self.indices.push(vertices_starting_index as u32 +0);
+        self.indices.push(vertices_starting_index as u32 +1);
+        self.indices.push(vertices_starting_index as u32 +2);
+        self.indices.push(vertices_starting_index as u32 +2);
+        self.indices.push(vertices_starting_index as u32 +3);
+        self.indices.push(vertices_starting_index as u32 +0);
 
         // If there is already a line segment connect the two together with another quad
         if vertices_starting_index >= 4 {
-            self.indices.push(vertices_starting_index as u16 -1);
-            self.indices.push(vertices_starting_index as u16 -2);
-            self.indices.push(vertices_starting_index as u16 +1);
-            self.indices.push(vertices_starting_index as u16 +1);
-            self.indices.push(vertices_starting_index as u16 +0);
-            self.indices.push(vertices_starting_index as u16 -1);
+            self.indices.push(vertices_starting_index as u32 -1);
+            self.indices.push(vertices_starting_index as u32 -2);
+            self.indices.push(vertices_starting_index as u32 +1);
+            self.indices.push(vertices_starting_index as u32 +1);
+            self.indices.push(vertices_starting_index as u32 +0);
+            self.indices.push(vertices_starting_index as u32 -1);
         }
     }
 
     pub fn new(points: &[Point], width: f32, texture: Option<String>, color: Option<&[f32;4]>) -> Self {
         assert!(points.len() >= 2);
-        let indices = vec![];
+        let indices = Indices::new();
         let vertices = vec![];
         let mut result = Self {
             indices,
@@ -164,8 +165,8 @@ impl LineStrip {
 }
 
 impl<'a> renderer::Drawable<'a> for LineStrip {
-    fn get_vertex_information(&'a self) -> (&'a[u16], &'a[Vertex]) {
-        (&self.indices, &self.vertices)
+    fn get_vertex_information(&'a self) -> (IndexSlice<'a>, &'a[Vertex]) {
+        (self.indices.as_slice(), &self.vertices)
     }
 
     fn get_texture_name(&self) -> Option<String> {
@@ -1,6 +1,7 @@
 use super::super::vertex::*;
 use super::super::point::*;
 use super::super::renderer;
+use super::super::indices::*;
 
 use super::helper_functions::gen_tex_coords;
 
@@ -22,10 +23,10 @@ impl Rectangle {
         match color {
             Some(col) => {
                 vertices = [
-                    Vertex { position: Point{x:top_left.x,     y:top_left.y},     texture_binding:-1, tex_coords_or_color: *col },
-                    Vertex { position: Point{x:bottom_right.x, y:top_left.y},     texture_binding:-1, tex_coords_or_color: *col },
-                    Vertex { position: Point{x:bottom_right.x, y:bottom_right.y}, texture_binding:-1, tex_coords_or_color: *col },
-                    Vertex { position: Point{x:top_left.x,     y:bottom_right.y}, texture_binding:-1, tex_coords_or_color: *col },
+                    Vertex { position: Point{x:top_left.x,     y:top_left.y},     texture_binding:-1, tex_coords_or_color: *col, sampler: DEFAULT_SAMPLER, transform_id: IDENTITY_TRANSFORM_ID },
+                    Vertex { position: Point{x:bottom_right.x, y:top_left.y},     texture_binding:-1, tex_coords_or_color: *col, sampler: DEFAULT_SAMPLER, transform_id: IDENTITY_TRANSFORM_ID },
+                    Vertex { position: Point{x:bottom_right.x, y:bottom_right.y}, texture_binding:-1, tex_coords_or_color: *col, sampler: DEFAULT_SAMPLER, transform_id: IDENTITY_TRANSFORM_ID },
+                    Vertex { position: Point{x:top_left.x,     y:bottom_right.y}, texture_binding:-1, tex_coords_or_color: *col, sampler: DEFAULT_SAMPLER, transform_id: IDENTITY_TRANSFORM_ID },
                 ];
                 if texture.is_some() {
                     // Placeholder; replace wth a proper error message later
@@ -39,10 +40,10 @@ impl Rectangle {
             }
             None => {
                 vertices = [
-                    Vertex { position: Point{x:top_left.x,     y:top_left.y},     texture_binding:0, tex_coords_or_color: [0.0, 0.0, 0.0, 1.0] },
-                    Vertex { position: Point{x:bottom_right.x, y:top_left.y},     texture_binding:0, tex_coords_or_color: [1.0, 0.0, 0.0, 1.0] },
-                    Vertex { position: Point{x:bottom_right.x, y:bottom_right.y}, texture_binding:0, tex_coords_or_color: [1.0, 1.0, 0.0, 1.0] },
-                    Vertex { position: Point{x:top_left.x,     y:bottom_right.y}, texture_binding:0, tex_coords_or_color: [0.0, 1.0, 0.0, 1.0] },
+                    Vertex { position: Point{x:top_left.x,     y:top_left.y},     texture_binding:0, tex_coords_or_color: [0.0, 0.0, 0.0, 1.0], sampler: DEFAULT_SAMPLER, transform_id: IDENTITY_TRANSFORM_ID },
+                    Vertex { position: Point{x:bottom_right.x, y:top_left.y},     texture_binding:0, tex_coords_or_color: [1.0, 0.0, 0.0, 1.0], sampler: DEFAULT_SAMPLER, transform_id: IDENTITY_TRANSFORM_ID },
+                    Vertex { position: Point{x:bottom_right.x, y:bottom_right.y}, texture_binding:0, tex_coords_or_color: [1.0, 1.0, 0.0, 1.0], sampler: DEFAULT_SAMPLER, transform_id: IDENTITY_TRANSFORM_ID },
+                    Vertex { position: Point{x:top_left.x,     y:bottom_right.y}, texture_binding:0, tex_coords_or_color: [0.0, 1.0, 0.0, 1.0], sampler: DEFAULT_SAMPLER, transform_id: IDENTITY_TRANSFORM_ID },
                 ];
             }
         }
@@ -56,8 +57,8 @@ impl Rectangle {
 }
 
 impl<'a> renderer::Drawable<'a> for Rectangle {
-    fn get_vertex_information(&'a self) -> (&'a[u16], &'a[Vertex]) {
-        (&self.indices, &self.vertices)
+    fn get_vertex_information(&'a self) -> (IndexSlice<'a>, &'a[Vertex]) {
+        (IndexSlice::U16(&self.indices), &self.vertices)
     }
 
     fn get_texture_name(&self) -> Option<String> {
@@ -83,9 +84,9 @@ impl Triangle {
         match color {
             Some(col) => {
                 vertices = [
-                    Vertex { position: p0, texture_binding:-1, tex_coords_or_color: *col },
-                    Vertex { position: p1, texture_binding:-1, tex_coords_or_color: *col },
-                    Vertex { position: p2, texture_binding:-1, tex_coords_or_color: *col },
+                    Vertex { position: p0, texture_binding:-1, tex_coords_or_color: *col, sampler: DEFAULT_SAMPLER, transform_id: IDENTITY_TRANSFORM_ID },
+                    Vertex { position: p1, texture_binding:-1, tex_coords_or_color: *col, sampler: DEFAULT_SAMPLER, transform_id: IDENTITY_TRANSFORM_ID },
+                    Vertex { position: p2, texture_binding:-1, tex_coords_or_color: *col, sampler: DEFAULT_SAMPLER, transform_id: IDENTITY_TRANSFORM_ID },
                 ];
                 if texture.is_some() {
                     // Placeholder; replace wth a proper error message later
@@ -99,9 +100,9 @@ impl Triangle {
             }
             None => {
                 vertices = [
-                    Vertex { position: p0, texture_binding:0, tex_coords_or_color: [0.0, 0.0, 0.0, 1.0] },
-                    Vertex { position: p1, texture_binding:0, tex_coords_or_color: [0.0, 0.0, 0.0, 1.0] },
-                    Vertex { position: p2, texture_binding:0, tex_coords_or_color: [0.0, 0.0, 0.0, 1.0] },
+                    Vertex { position: p0, texture_binding:0, tex_coords_or_color: [0.0, 0.0, 0.0, 1.0], sampler: DEFAULT_SAMPLER, transform_id: IDENTITY_TRANSFORM_ID },
+                    Vertex { position: p1, texture_binding:0, tex_coords_or_color: [0.0, 0.0, 0.0, 1.0], sampler: DEFAULT_SAMPLER, transform_id: IDENTITY_TRANSFORM_ID },
+                    Vertex { position: p2, texture_binding:0, tex_coords_or_color: [0.0, 0.0, 0.0, 1.0], sampler: DEFAULT_SAMPLER, transform_id: IDENTITY_TRANSFORM_ID },
                 ];
                 gen_tex_coords(&mut vertices);
             }
@@ -116,8 +117,8 @@ impl Triangle {
 }
 
 impl<'a> renderer::Drawable<'a> for Triangle {
-    fn get_vertex_information(&'a self) -> (&'a[u16], &'a[Vertex]) {
-        (&self.indices, &self.vertices)
+    fn get_vertex_information(&'a self) -> (IndexSlice<'a>, &'a[Vertex]) {
+        (IndexSlice::U16(&self.indices), &self.vertices)
     }
 
     fn get_texture_name(&self) -> Option<String> {
@@ -158,21 +159,21 @@ fn is_reflex_angle(a:Point, b:Point, c:Point) -> bool {
 fn ear_clipping(
     original_points: &[Point], 
     points: &mut Vec<usize>,
-    resulting_indices: &mut Vec<u16>) -> bool 
+    resulting_indices: &mut Indices) -> bool 
 {
     // println!("ec: {}", points.len());
     if points.len() == 4 {
-        resulting_indices.push(points[0] as u16);
-        resulting_indices.push(points[1] as u16);
-        resulting_indices.push(points[3] as u16);
-        resulting_indices.push(points[1] as u16);
-        resulting_indices.push(points[2] as u16);
-        resulting_indices.push(points[3] as u16);
+        resulting_indices.push(points[0] as u32);
+        resulting_indices.push(points[1] as u32);
+        resulting_indices.push(points[3] as u32);
+        resulting_indices.push(points[1] as u32);
+        resulting_indices.push(points[2] as u32);
+        resulting_indices.push(points[3] as u32);
         return true;
     } else if points.len() == 3 {
-        resulting_indices.push(points[0] as u16);
-        resulting_indices.push(points[1] as u16);
-        resulting_indices.push(points[2] as u16);
+        resulting_indices.push(points[0] as u32);
+        resulting_indices.push(points[1] as u32);
+        resulting_indices.push(points[2] as u32);
         return true;
     }
     for i in 0..(points.len() as isize) {
@@ -205,9 +206,9 @@ fn ear_clipping(
             }
             if is_ear {
                 points.remove(i as usize);
-                resulting_indices.push(p_bef_idx as u16);
-                resulting_indices.push(p_cur_idx as u16);
-                resulting_indices.push(p_aft_idx as u16);
+                resulting_indices.push(p_bef_idx as u32);
+                resulting_indices.push(p_cur_idx as u32);
+                resulting_indices.push(p_aft_idx as u32);
                 // println!("resulting_indices: {:?}", resulting_indices);
                 return ear_clipping(original_points, points, resulting_indices);
             }
@@ -217,7 +218,7 @@ fn ear_clipping(
 }
 
 pub struct Polygon {
-    indices: Vec<u16>,
+    indices: Indices,
     vertices: Vec<Vertex>,
     texture:Option<String>,
 }
@@ -227,7 +228,7 @@ impl Polygon {
     // The points represent the polygon in a ccw order
     // Failure to follow the above will result in a nonexistent or malformed shape
     pub fn new(points: &[Point], texture:Option<String>, color:Option<&[f32;4]>) -> Self {
-        let mut indices: Vec<u16> = vec![];
+        let mut indices = Indices::new();
         let mut point_indexes: Vec<usize> = (0..points.len()).collect();
         // println!("{}", ear_clipping(points, &mut point_indexes, &mut indices));
         ear_clipping(points, &mut point_indexes, &mut indices);
@@ -235,7 +236,7 @@ impl Polygon {
 
         let mut vertices: Vec<Vertex> = vec![];
         for point in points {
-            vertices.push(Vertex { position: *point, texture_binding:-1, tex_coords_or_color: [0.0,0.0,0.0,1.0] });
+            vertices.push(Vertex { position: *point, texture_binding:-1, tex_coords_or_color: [0.0,0.0,0.0,1.0], sampler: DEFAULT_SAMPLER, transform_id: IDENTITY_TRANSFORM_ID });
         }
 
         match color {
@@ -267,8 +268,8 @@ impl Polygon {
 }
 
 impl<'a> renderer::Drawable<'a> for Polygon {
-    fn get_vertex_information(&'a self) -> (&'a[u16], &'a[Vertex]) {
-        (&self.indices, &self.vertices)
+    fn get_vertex_information(&'a self) -> (IndexSlice<'a>, &'a[Vertex]) {
+        (self.indices.as_slice(), &self.vertices)
     }
 
     fn get_texture_name(&self) -> Option<String> {
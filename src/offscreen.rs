@@ -0,0 +1,111 @@
+use super::renderer::DrawableFrame;
+
+// A texture that can be rendered into without a window and read back to the cpu
+pub struct OffscreenTarget {
+    texture: wgpu::Texture,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+}
+
+impl OffscreenTarget {
+    pub fn new(device: &wgpu::Device, width: u32, height: u32, format: wgpu::TextureFormat) -> Self {
+        let texture = device.create_texture(
+            &wgpu::TextureDescriptor {
+                label: Some("offscreen_target"),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth: 1,
+                },
+                array_layer_count: 1,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::COPY_SRC,
+            },
+        );
+
+        Self {
+            texture,
+            width,
+            height,
+            format,
+        }
+    }
+
+    // The returned frame can be passed to Renderer::begin_render
+    pub fn create_frame(&self) -> DrawableFrame {
+        DrawableFrame::from_texture_view(Box::new(self.texture.create_default_view()), self.width, self.height)
+    }
+
+    // Copies the contents of the target into an image
+    // Blocks until the gpu has finished every previously submitted command
+    pub fn read_pixels(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<image::RgbaImage, wgpu::BufferAsyncErr> {
+        // Rows in the copy buffer have to be aligned to 256 bytes
+        let unpadded_bytes_per_row = 4*self.width;
+        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(256) * 256;
+        let buffer_size = (padded_bytes_per_row as u64 * self.height as u64) as wgpu::BufferAddress;
+
+        let buffer = device.create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some("offscreen readback buffer"),
+                size: buffer_size,
+                usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+            }
+        );
+
+        let mut encoder = device.create_command_encoder(
+            &wgpu::CommandEncoderDescriptor {
+                label: Some("offscreen readback encoder"),
+            },
+        );
+
+        encoder.copy_texture_to_buffer(
+            wgpu::TextureCopyView {
+                texture: &self.texture,
+                mip_level: 0,
+                array_layer: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::BufferCopyView {
+                buffer: &buffer,
+                offset: 0,
+                bytes_per_row: padded_bytes_per_row,
+                rows_per_image: self.height,
+            },
+            wgpu::Extent3d {
+                width: self.width,
+                height: self.height,
+                depth: 1,
+            },
+        );
+
+        queue.submit(&[encoder.finish()]);
+
+        let future_read_mapping = buffer.map_read(0, buffer_size);
+        device.poll(wgpu::Maintain::Wait);
+        use futures::executor::block_on;
+        let read_mapping = block_on(future_read_mapping)?;
+        let data = read_mapping.as_slice();
+
+        // Bgra formats have to be swizzled back into rgba
+        let is_bgra = matches!(self.format, wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb);
+
+        let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * self.height) as usize);
+        for row in 0..self.height as usize {
+            let row_start = row * padded_bytes_per_row as usize;
+            for pixel in data[row_start..row_start + unpadded_bytes_per_row as usize].chunks(4) {
+                if is_bgra {
+                    pixels.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]);
+                } else {
+                    pixels.extend_from_slice(pixel);
+                }
+            }
+        }
+
+        // The buffer always has exactly width*height pixels
+        Ok(image::RgbaImage::from_raw(self.width, self.height, pixels).unwrap())
+    }
+}
@@ -0,0 +1,102 @@
+// mod renderer;
+use super::renderer::ShaderSource;
+
+// mod texture;
+use super::texture::TextureRegion;
+
+pub const POST_VERTEX_SPIRV: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/post.vs.spv"));
+const BLUR_SPIRV: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/post_blur.fs.spv"));
+const BRIGHT_SPIRV: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/post_bright.fs.spv"));
+const BLOOM_SPIRV: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/post_bloom.fs.spv"));
+const VIGNETTE_SPIRV: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/post_vignette.fs.spv"));
+const COLOR_GRADING_SPIRV: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/post_color_grading.fs.spv"));
+const CRT_SPIRV: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/post_crt.fs.spv"));
+
+// Full screen effects that end_render applies to the frame in order, see Renderer::set_post_effects
+#[allow(dead_code)]
+#[derive(Clone, Debug)]
+pub enum PostEffect {
+    // Gaussian blur, the radius is in pixels
+    Blur {
+        radius: f32,
+    },
+    // Parts brighter than the threshold glow, the radius is the one of the blur that spreads the glow
+    Bloom {
+        threshold: f32,
+        radius: f32,
+        intensity: f32,
+    },
+    // Darkens the corners, 1 makes them black
+    Vignette {
+        strength: f32,
+    },
+    // Maps colors through a lookup table, see post_color_grading.fs for its layout
+    // The table is looked up when the effect is set, so it has to be loaded first
+    ColorGrading {
+        lut_texture: String,
+    },
+    Crt {
+        scanline_strength: f32,
+    },
+    // A fragment shader with the inputs described in post_vignette.fs
+    // Uniform data is bound to set 2, binding 0; without it the shader can't declare set 2
+    Custom {
+        fragment_shader: ShaderSource,
+        uniform_data: Option<Vec<u8>>,
+    },
+}
+
+// One full screen draw of an effect
+pub struct PostPassDescriptor {
+    pub fragment_shader: ShaderSource,
+    pub uniform_data: Option<Vec<u8>>,
+}
+
+impl PostPassDescriptor {
+    fn new(spirv: &'static [u8], uniforms: [f32; 4]) -> Self {
+        Self {
+            fragment_shader: ShaderSource::Spirv(spirv),
+            // Uniform blocks are padded to 16 bytes
+            uniform_data: Some(bytemuck::cast_slice(&uniforms).to_vec()),
+        }
+    }
+}
+
+impl PostEffect {
+    // get_texture_region finds the region of a loaded texture by its name
+    pub fn get_passes<F: Fn(&str) -> TextureRegion>(&self, get_texture_region: F) -> Vec<PostPassDescriptor> {
+        match self {
+            PostEffect::Blur { radius } => vec![
+                PostPassDescriptor::new(BLUR_SPIRV, [1.0, 0.0, *radius, 0.0]),
+                PostPassDescriptor::new(BLUR_SPIRV, [0.0, 1.0, *radius, 0.0]),
+            ],
+            PostEffect::Bloom { threshold, radius, intensity } => vec![
+                PostPassDescriptor::new(BRIGHT_SPIRV, [*threshold, 0.0, 0.0, 0.0]),
+                PostPassDescriptor::new(BLUR_SPIRV, [1.0, 0.0, *radius, 0.0]),
+                PostPassDescriptor::new(BLUR_SPIRV, [0.0, 1.0, *radius, 0.0]),
+                PostPassDescriptor::new(BLOOM_SPIRV, [*intensity, 0.0, 0.0, 0.0]),
+            ],
+            PostEffect::Vignette { strength } => vec![
+                PostPassDescriptor::new(VIGNETTE_SPIRV, [*strength, 0.0, 0.0, 0.0]),
+            ],
+            PostEffect::ColorGrading { lut_texture } => {
+                let region = get_texture_region(lut_texture);
+                let mut uniform_data = bytemuck::cast_slice(&[region.uv_offset[0], region.uv_offset[1], region.uv_scale[0], region.uv_scale[1]]).to_vec();
+                uniform_data.extend_from_slice(bytemuck::cast_slice(&[region.layer as i32, 0, 0, 0]));
+                vec![PostPassDescriptor {
+                    fragment_shader: ShaderSource::Spirv(COLOR_GRADING_SPIRV),
+                    uniform_data: Some(uniform_data),
+                }]
+            },
+            PostEffect::Crt { scanline_strength } => vec![
+                PostPassDescriptor::new(CRT_SPIRV, [*scanline_strength, 0.0, 0.0, 0.0]),
+            ],
+            PostEffect::Custom { fragment_shader, uniform_data } => vec![
+                PostPassDescriptor {
+                    fragment_shader: fragment_shader.clone(),
+                    uniform_data: uniform_data.clone(),
+                },
+            ],
+        }
+    }
+}
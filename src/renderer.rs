@@ -1,123 +1,685 @@
 // mod texture;
-use super::texture::Texture;
+use super::texture::{TextureArray, TextureRegion, Sampler, MAX_TEXTURE_LAYERS};
+
+// mod texture_atlas;
+use super::texture_atlas::TextureAtlas;
 
 // mod vertex;
 use super::vertex::Vertex;
 
+// mod mesh_store;
+use super::mesh_store::{MeshStore, MeshHandle};
+
+// mod instance;
+use super::instance::{Instance, DrawInstance, padded_columns};
+
 // mod camera;
 use super::camera::*;
 
 // mod uniforms;
 use super::uniforms::Uniforms;
 
+// mod offscreen;
+use super::offscreen::OffscreenTarget;
+
+// mod blend_mode;
+use super::blend_mode::BlendMode;
+
+// mod clip;
+use super::clip::{ClipStack, ScissorRect};
+
+// mod indices;
+use super::indices::IndexSlice;
+
+// mod post_processing;
+use super::post_processing::{PostEffect, POST_VERTEX_SPIRV};
+
+// mod viewport;
+use super::viewport::Viewport;
+
+// mod frame_stats;
+use super::frame_stats::{FrameStats, FlushReason, StatsAggregator};
+
+// mod staging;
+use super::staging::{StagingRing, StreamedBuffer, create_target_buffer, get_aligned_size};
+
+use cgmath::{Matrix3, SquareMatrix};
+
 use std::collections::{
     HashMap,
+    HashSet,
     hash_map,
 };
+use std::ops::Range;
+use std::fmt;
+use std::time::Instant;
+
+pub const PLACEHOLDER_TEXTURE_NAME:&str = "placeholder_texture.png";
+// The material made from shader.vs and shader.fs, used by draws that don't pick one
+pub const DEFAULT_MATERIAL_NAME:&str = "default";
+const DEFAULT_MATERIAL:usize = 0;
+// Compiled by build.rs
+const DEFAULT_VERTEX_SPIRV:&[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/shader.vs.spv"));
+const DEFAULT_FRAGMENT_SPIRV:&[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/shader.fs.spv"));
+const MAX_UINT16_INDEXED_VERTICES:u32 = u16::MAX as u32 + 1;
+// Number of draw transforms the transform buffer starts with, it grows when a batch needs more
+const INITIAL_TRANSFORM_CAPACITY:u32 = 256;
+const INITIAL_INSTANCE_CAPACITY:u32 = 256;
+const INITIAL_CAMERA_CAPACITY:u32 = 4;
+// Cameras are bound with a dynamic offset into the uniform buffer, which has to be aligned like this
+const UNIFORM_SLOT_SIZE:wgpu::BufferAddress = wgpu::BIND_BUFFER_ALIGNMENT;
+const INITIAL_MATERIAL_STAGING_SIZE:wgpu::BufferAddress = 1024;
+// Clip masks are drawn into the stencil part, depth is unused
+const STENCIL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth24PlusStencil8;
+// Windows of different sizes each keep their attachments while frames alternate between them
+const MAX_FRAME_ATTACHMENTS: usize = 4;
+
+#[derive(Debug)]
+pub enum RendererError {
+    NoAdapter,
+    // Mapping a buffer failed, usually because the device was lost
+    BufferMapping,
+    InvalidDescriptor(String),
+    ShaderCompilation(String),
+    TextureDecoding(image::ImageError),
+    TooManyTextures,
+    UnknownMaterial(String),
+    UniformDataTooLarge {
+        size: u64,
+        capacity: u64,
+    },
+    RenderInProgress,
+    NoRenderInProgress,
+    UnknownRenderTarget(String),
+    // The mesh was already removed or belongs to another renderer
+    UnknownMesh,
+    // pop_clip was called more often than clips were pushed
+    NoClipToPop,
+    // A pass earlier in the frame already used the target's old contents
+    TargetSampledBeforeRender(String),
+    // Render targets are copied into a layer of the texture array, so they can't be bigger than a layer
+    InvalidRenderTargetSize {
+        width: u32,
+        height: u32,
+        layer_size: u32,
+    },
+    // Atlas images have to fit into a page with their padding and can't be empty
+    InvalidAtlasImageSize {
+        width: u32,
+        height: u32,
+        max_size: u32,
+    },
+    // Textures keep their size in a layer of the texture array, so they can't be bigger than a layer
+    InvalidTextureSize {
+        width: u32,
+        height: u32,
+        layer_size: u32,
+    },
+    // Offscreen targets can be as big as the gpu allows but not empty
+    InvalidTargetSize {
+        width: u32,
+        height: u32,
+    },
+}
+
+impl fmt::Display for RendererError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RendererError::NoAdapter => {
+                write!(f, "No graphics adapter is available")
+            },
+            RendererError::BufferMapping => {
+                write!(f, "A buffer could not be mapped, the device might have been lost")
+            },
+            RendererError::InvalidDescriptor(reason) => {
+                write!(f, "Invalid renderer descriptor: {}", reason)
+            },
+            RendererError::ShaderCompilation(message) => {
+                write!(f, "Shader compilation failed: {}", message)
+            },
+            RendererError::TextureDecoding(error) => {
+                write!(f, "Texture could not be decoded: {}", error)
+            },
+            RendererError::TooManyTextures => {
+                write!(f, "The texture array is full, no more than {} layers can be loaded", MAX_TEXTURE_LAYERS)
+            },
+            RendererError::UnknownMaterial(name) => {
+                write!(f, "No material called {} has been registered", name)
+            },
+            RendererError::UniformDataTooLarge { size, capacity } => {
+                write!(f, "{} bytes of uniform data don't fit into the material's {} byte uniform buffer", size, capacity)
+            },
+            RendererError::RenderInProgress => {
+                write!(f, "The previous render has not ended")
+            },
+            RendererError::NoRenderInProgress => {
+                write!(f, "begin_render has to be called first")
+            },
+            RendererError::NoClipToPop => {
+                write!(f, "pop_clip was called without a pushed scissor rectangle or clip mask")
+            },
+            RendererError::TargetSampledBeforeRender(name) => {
+                write!(f, "Render target {} was used before it was rendered in this frame, it has to be rendered before the passes that use it", name)
+            },
+            RendererError::UnknownMesh => {
+                write!(f, "The mesh handle doesn't refer to a mesh of this renderer")
+            },
+            RendererError::UnknownRenderTarget(name) => {
+                write!(f, "No render target called {} has been created", name)
+            },
+            RendererError::InvalidRenderTargetSize { width, height, layer_size } => {
+                write!(f, "A render target has to be between 1x1 and {}x{} pixels, not {}x{}", layer_size, layer_size, width, height)
+            },
+            RendererError::InvalidAtlasImageSize { width, height, max_size } => {
+                write!(f, "An atlas image has to be between 1x1 and {}x{} pixels, not {}x{}", max_size, max_size, width, height)
+            },
+            RendererError::InvalidTextureSize { width, height, layer_size } => {
+                write!(f, "A texture has to be between 1x1 and {}x{} pixels, not {}x{}", layer_size, layer_size, width, height)
+            },
+            RendererError::InvalidTargetSize { width, height } => {
+                write!(f, "An offscreen target has to be at least 1x1 pixels, not {}x{}", width, height)
+            },
+        }
+    }
+}
+
+impl From<wgpu::BufferAsyncErr> for RendererError {
+    fn from(_: wgpu::BufferAsyncErr) -> RendererError {
+        RendererError::BufferMapping
+    }
+}
+
+impl From<image::ImageError> for RendererError {
+    fn from(error: image::ImageError) -> RendererError {
+        RendererError::TextureDecoding(error)
+    }
+}
+
+fn index_format_size(index_format: wgpu::IndexFormat) -> u64 {
+    match index_format {
+        wgpu::IndexFormat::Uint16 => std::mem::size_of::<u16>() as u64,
+        wgpu::IndexFormat::Uint32 => std::mem::size_of::<u32>() as u64,
+    }
+}
 
-const MAX_TEXTURES_PER_DRAW_CALL:usize = 10;
-const PLACEHOLDER_TEXTURE_NAME:&str = "placeholder_texture.png";
+// Where the code of a shader comes from
+#[derive(Clone, Debug)]
+pub enum ShaderSource {
+    // Path of a GLSL file that is compiled at runtime, it is recompiled when it changes if watch_shaders is on
+    File(String),
+    // SPIR-V compiled ahead of time, build.rs compiles everything in src/shaders into OUT_DIR as <name>.spv
+    Spirv(&'static [u8]),
+}
+
+impl ShaderSource {
+    fn get_path(&self) -> Option<&str> {
+        match self {
+            ShaderSource::File(path) => Some(path),
+            ShaderSource::Spirv(_) => None,
+        }
+    }
+}
 
 mod shader {
+    use super::{ShaderSource, RendererError};
     use std::fs;
-    pub fn create_fragment_shader(fragment_name: &str, device: &wgpu::Device) -> wgpu::ShaderModule {
-        let fs_src = fs::read_to_string(fragment_name).expect("Could not load fragment shader");
-        let fs_spirv = glsl_to_spirv::compile(&fs_src, glsl_to_spirv::ShaderType::Fragment).unwrap();
-        let fs_data = wgpu::read_spirv(fs_spirv).unwrap();
+    use std::time::SystemTime;
+    // The errors contain the messages of the glsl compiler
+    fn create_shader(source: &ShaderSource, shader_type: glsl_to_spirv::ShaderType, device: &wgpu::Device) -> Result<wgpu::ShaderModule, RendererError> {
+        let data = match source {
+            ShaderSource::File(path) => {
+                let src = fs::read_to_string(path).map_err(|e| RendererError::ShaderCompilation(format!("Could not load {}: {}", path, e)))?;
+                let spirv = glsl_to_spirv::compile(&src, shader_type).map_err(|e| RendererError::ShaderCompilation(format!("{}: {}", path, e)))?;
+                wgpu::read_spirv(spirv).map_err(|e| RendererError::ShaderCompilation(format!("{}: {}", path, e)))?
+            },
+            ShaderSource::Spirv(bytes) => wgpu::read_spirv(std::io::Cursor::new(bytes)).map_err(|e| RendererError::ShaderCompilation(format!("Invalid SPIR-V: {}", e)))?,
+        };
 
-        device.create_shader_module(&fs_data)
+        Ok(device.create_shader_module(&data))
     }
-    pub fn create_vertex_shader(vertex_name: &str, device: &wgpu::Device) -> wgpu::ShaderModule {
-        let vs_src = fs::read_to_string(vertex_name).expect("Could not load fragment shader");
-        let vs_spirv = glsl_to_spirv::compile(&vs_src, glsl_to_spirv::ShaderType::Vertex).unwrap();
-        let vs_data = wgpu::read_spirv(vs_spirv).unwrap();
-        
-        device.create_shader_module(&vs_data)
+    pub fn create_fragment_shader(source: &ShaderSource, device: &wgpu::Device) -> Result<wgpu::ShaderModule, RendererError> {
+        create_shader(source, glsl_to_spirv::ShaderType::Fragment, device)
+    }
+    pub fn create_vertex_shader(source: &ShaderSource, device: &wgpu::Device) -> Result<wgpu::ShaderModule, RendererError> {
+        create_shader(source, glsl_to_spirv::ShaderType::Vertex, device)
+    }
+    pub fn create_shaders(vertex_source: &ShaderSource, fragment_source: &ShaderSource, device: &wgpu::Device) -> Result<(wgpu::ShaderModule, wgpu::ShaderModule), RendererError> {
+        Ok((create_vertex_shader(vertex_source, device)?, create_fragment_shader(fragment_source, device)?))
+    }
+    // The latest modification time of the shader files, None if there are none or none of them can be read
+    pub fn get_modified_time(sources: &[&ShaderSource]) -> Option<SystemTime> {
+        sources.iter()
+            .filter_map(|source| source.get_path())
+            .filter_map(|path| fs::metadata(path).and_then(|metadata| metadata.modified()).ok())
+            .max()
     }
 }
 
 pub trait Drawable<'a> {
-    fn get_vertex_information(&'a self) -> (&'a[u16], &'a[Vertex]);
+    fn get_vertex_information(&'a self) -> (IndexSlice<'a>, &'a[Vertex]);
     fn get_texture_name(&self) -> Option<String>;
 }
 
+// A piece of a shape that was too big to fit into a single batch
+struct ShapePart {
+    indices: Vec<u32>,
+    vertices: Vec<Vertex>,
+}
+
+// Splits a shape along its triangles into parts that each have at most max_vertices vertices and max_indices indices
+fn split_shape(indices: IndexSlice, vertices: &[Vertex], max_vertices: u32, max_indices: u32) -> Vec<ShapePart> {
+    let mut parts = vec![];
+    let mut part = ShapePart { indices: vec![], vertices: vec![] };
+    // Maps indices of the shape to indices of the current part
+    let mut remapped_indices: HashMap<u32, u32> = HashMap::new();
+
+    for triangle in 0..indices.len()/3 {
+        let triangle_indices = [indices.get(3*triangle), indices.get(3*triangle+1), indices.get(3*triangle+2)];
+        let new_vertices = triangle_indices.iter().filter(|i| !remapped_indices.contains_key(i)).count();
+        if part.vertices.len() + new_vertices > max_vertices as usize || part.indices.len() + 3 > max_indices as usize {
+            parts.push(part);
+            part = ShapePart { indices: vec![], vertices: vec![] };
+            remapped_indices.clear();
+        }
+        for index in &triangle_indices {
+            let part_vertices = &mut part.vertices;
+            let remapped_index = *remapped_indices.entry(*index).or_insert_with(|| {
+                part_vertices.push(vertices[*index as usize]);
+                part_vertices.len() as u32 - 1
+            });
+            part.indices.push(remapped_index);
+        }
+    }
+    if !part.indices.is_empty() {
+        parts.push(part);
+    }
+    parts
+}
+
+// A shape or mesh that has been drawn but not put into a batch yet
+struct QueuedDraw {
+    layer: i32,
+    blend_mode: BlendMode,
+    // Index into the renderer's materials
+    material: usize,
+    // Id of the clip state in the renderer's ClipStack
+    clip: usize,
+    // Index into the renderer's cameras
+    camera: usize,
+    viewport: Option<Viewport>,
+    geometry: QueuedGeometry,
+}
+
+enum QueuedGeometry {
+    // The vertices point to their texture layer and sampler, the transform is applied on the gpu
+    Shape {
+        indices: Vec<u32>,
+        vertices: Vec<Vertex>,
+        transform: Option<[[f32; 4]; 3]>,
+    },
+    // The mesh is transformed on the gpu, nothing is drawn if it was removed in the meantime
+    Mesh {
+        mesh: MeshHandle,
+        instance: Instance,
+    },
+    // Untransformed vertices with tex coords in 0..1, drawn once for every instance
+    Instanced {
+        indices: Vec<u32>,
+        vertices: Vec<Vertex>,
+        instances: Vec<Instance>,
+    },
+}
+
+// What a batch draws, in order
+enum BatchItem {
+    // Indices in the batch's index buffer, drawn with a range of the batch instances
+    Shapes {
+        indices: Range<u32>,
+        instances: Range<u32>,
+    },
+    // A mesh with the batch instance at this index
+    Mesh {
+        mesh: MeshHandle,
+        instance: u32,
+    },
+}
+
+// Geometry that stays on the gpu between frames
+struct Mesh {
+    parts: Vec<MeshPart>,
+    texture_name: Option<String>,
+    // The region of the shape's texture, the tex coords of its vertices stay in 0..1 so draws can replace it
+    // None for shapes without a texture name
+    texture_region: Option<TextureRegion>,
+    // The layers its vertices are textured with if there is no texture region, for the frame stats
+    texture_layers: Vec<u32>,
+}
+
+// Part of a mesh that can be drawn with a single draw call
+struct MeshPart {
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    num_vertices: u32,
+    num_indices: u32,
+}
+
+// A shape that restricts drawing to the pixels it covers, stored with transformed vertices
+// It is drawn with the camera and viewport that were current when it was pushed, like in the software renderer
+struct ClipMask {
+    indices: Vec<u32>,
+    vertices: Vec<Vertex>,
+    camera: usize,
+    viewport: Option<Viewport>,
+}
+
+// Mask geometry in the batch's buffers with the stencil value it is drawn on top of
+struct MaskDraw {
+    stencil_value: u32,
+    indices: Range<u32>,
+    camera: usize,
+    viewport: Option<Viewport>,
+}
+
+// Shaders, pipelines and custom uniforms of a registered material
+struct Material {
+    vertex_shader: ShaderSource,
+    fragment_shader: ShaderSource,
+    // When the shader files were last changed, used to notice edits while watch_shaders is on
+    shaders_modified: Option<std::time::SystemTime>,
+    settings: PipelineSettings,
+    // One pipeline per blend mode the material has been drawn with so far
+    pipelines: HashMap<BlendMode, wgpu::RenderPipeline>,
+    uniforms: Option<MaterialUniforms>,
+}
+
+struct MaterialUniforms {
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    size: u64,
+}
+
+// A full screen draw of a post effect, see post_processing.rs
+struct PostPass {
+    pipeline: wgpu::RenderPipeline,
+    uniforms: Option<MaterialUniforms>,
+    // The texture the first pass of an effect reads from stays available to the effect's later passes
+    starts_effect: bool,
+}
+
+// The frame is rendered into one of the textures, then the passes take turns rendering into the others
+// A third texture keeps the input of an effect around while the effect's passes ping-pong between the other two
+struct PostTargets {
+    textures: [wgpu::Texture; 3],
+    // The PostUniforms of post_vignette.fs, they only depend on the size
+    uniform_buffer: wgpu::Buffer,
+    width: u32,
+    height: u32,
+}
+
+// Attachments that have the same size as the frame
+struct FrameAttachments {
+    stencil: wgpu::Texture,
+    // Only used with multisampling, it is resolved into the frame
+    multisampled_color: Option<wgpu::Texture>,
+    width: u32,
+    height: u32,
+}
+
+// A texture that draws can be rendered into with render_to_target
+// The result is copied into its layer of the texture array, so shapes can use it like a loaded texture
+struct RenderTarget {
+    texture: wgpu::Texture,
+    width: u32,
+    height: u32,
+    layer: u32,
+    // Kept apart from the renderer's while rendering to the target, so the windows' attachments aren't dropped to make room
+    attachments: Option<FrameAttachments>,
+}
+
+// Only used by clip masks, drawn shapes are transformed on the gpu
+fn transform_vertices(vertices: &mut [Vertex], transformation: Option<&UsableTransform>) {
+    if let Some(transf) = transformation {
+        let transformation_matrix = transf.get_transformation_matrix();
+        for vertex in vertices {
+            UsableTransform::transform_point_with_matrix(&mut vertex.position, &transformation_matrix)
+        }
+    }
+}
+
+// Anything that can draw Drawables; implemented by both the gpu Renderer and the SoftwareRenderer
+pub trait ShapeRenderer {
+    fn draw<'a, T:Drawable<'a>>(&mut self, shape: &'a T, transformation: Option<&UsableTransform>);
+    fn set_sampler(&mut self, sampler: Option<Sampler>);
+    fn set_blend_mode(&mut self, blend_mode: BlendMode);
+    fn set_layer(&mut self, layer: i32);
+    fn create_mesh<'a, T:Drawable<'a>>(&mut self, shape: &'a T) -> MeshHandle;
+    fn draw_mesh(&mut self, mesh: MeshHandle, transformation: Option<&UsableTransform>, texture_name: Option<&str>);
+    fn draw_instanced<'a, T:Drawable<'a>>(&mut self, shape: &'a T, instances: &[DrawInstance]);
+    fn set_material(&mut self, name: Option<&str>);
+    fn update(&mut self, camera: &Camera);
+    fn set_viewport(&mut self, viewport: Option<Viewport>);
+    fn push_scissor_rect(&mut self, rect: ScissorRect);
+    fn push_clip_mask<'a, T:Drawable<'a>>(&mut self, shape: &'a T, transformation: Option<&UsableTransform>);
+    fn pop_clip(&mut self) -> Result<(), RendererError>;
+}
+
+enum FrameTarget {
+    TextureView(Box<wgpu::TextureView>),
+    SwapChainOutput(wgpu::SwapChainOutput),
+}
+
 pub struct DrawableFrame {
-    // There must always be exactly one Option::Some
-    // TODO: Convert to Union?
-    texture_view: Option<Box<wgpu::TextureView>>,
-    sc_output: Option<wgpu::SwapChainOutput>,
+    target: FrameTarget,
+    width: u32,
+    height: u32,
 }
 
 #[allow(dead_code)]
 impl DrawableFrame {
-    pub fn from_texture_view(texture_view: Box<wgpu::TextureView>) -> Self {
+    pub fn from_texture_view(texture_view: Box<wgpu::TextureView>, width: u32, height: u32) -> Self {
         Self {
-            texture_view: Some(texture_view),
-            sc_output: None,
+            target: FrameTarget::TextureView(texture_view),
+            width,
+            height,
         }
     }
 
-    pub fn from_sc_output(sc_output: wgpu::SwapChainOutput) -> Self {
+    pub fn from_sc_output(sc_output: wgpu::SwapChainOutput, width: u32, height: u32) -> Self {
         Self {
-            texture_view: None,
-            sc_output: Some(sc_output),
+            target: FrameTarget::SwapChainOutput(sc_output),
+            width,
+            height,
         }
     }
 
     fn get_frame(&self) -> &wgpu::TextureView {
-        match &self.texture_view {
-            Some(boxed_view) => &*boxed_view,
-            None => {
-                match &self.sc_output {
-                    Some(sc_output) => &sc_output.view,
-                    None => panic!(),
-                }
-            }
+        match &self.target {
+            FrameTarget::TextureView(boxed_view) => &*boxed_view,
+            FrameTarget::SwapChainOutput(sc_output) => &sc_output.view,
         }
     }
 }
 
+pub struct RendererDescriptor {
+    // Number of vertices and indices the buffers have room for before they have to grow
+    pub initial_vertices: u32,
+    pub initial_indices: u32,
+    // The buffers never grow past these; None lets them grow as much as needed
+    // Shapes that are bigger than the limits are split into several draw calls
+    pub max_vertices: Option<u32>,
+    pub max_indices: Option<u32>,
+    // With IndexFormat::Uint16 a single draw call can reference at most 65536 vertices, so batches are flushed earlier
+    // IndexFormat::Uint32 lifts that limit at the cost of twice the index buffer size
+    pub index_format: wgpu::IndexFormat,
+    pub format: wgpu::TextureFormat,
+    // Width and height of every layer in the texture array; loaded textures are resized to this
+    pub texture_layer_size: u32,
+    // Samples per pixel for multisample anti-aliasing: 1 (off), 2, 4 or 8
+    pub sample_count: u32,
+    // Loads shader.vs and shader.fs from this directory instead of using the ones built into the binary
+    pub shader_directory: Option<String>,
+    // Recompile the shaders of every material when their files change, meant for development
+    // Only shaders loaded from files can change
+    pub watch_shaders: bool,
+}
+
+// Shaders of a material registered with Renderer::register_material
+pub struct MaterialDescriptor<'a> {
+    // The fragment shader gets the same inputs as shaders/shader.fs
+    pub fragment_shader: ShaderSource,
+    // None uses the vertex shader of the default material
+    pub vertex_shader: Option<ShaderSource>,
+    // Contents of the uniform block at set 3, binding 0; it has to match the block's std140 layout
+    pub uniform_data: Option<&'a [u8]>,
+}
+
 pub struct Renderer {
     device: wgpu::Device,
     queue: wgpu::Queue,
 
-    render_pipeline: wgpu::RenderPipeline,
-
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+    watch_shaders: bool,
+    // Compile errors of shaders reloaded by begin_render, until take_shader_errors is called
+    shader_errors: Vec<RendererError>,
+
+    // The default material is always first
+    materials: Vec<Material>,
+    // Maps material names to their index in materials
+    material_ids: HashMap<String, usize>,
+    uniform_bind_group_layout: wgpu::BindGroupLayout,
+    texture_sampler_bind_group_layout: wgpu::BindGroupLayout,
+    material_bind_group_layout: wgpu::BindGroupLayout,
+    // Blend mode and material of the current batch
+    batch_blend_mode: BlendMode,
+    batch_material: usize,
+    mask_pipeline: wgpu::RenderPipeline,
+    // One set per frame size, the most recently used one is last
+    frame_attachments: Vec<FrameAttachments>,
+
+    clip_stack: ClipStack,
+    clip_masks: Vec<ClipMask>,
+    // Clip state of the current batch
+    batch_clip: usize,
+    // Index into cameras
+    batch_camera: usize,
+    batch_viewport: Option<Viewport>,
+    // The masks that are in the stencil buffer once the masks in mask_draws have been drawn
+    stencil_masks: Vec<usize>,
+    // Mask geometry at the start of the current batch
+    mask_draws: Vec<MaskDraw>,
+    clear_stencil: bool,
+
+    // The batch is written into the staging rings and copied into these buffers when it is rendered
     vertex_buffer: wgpu::Buffer,
-    vertex_buffer_write_mapping: wgpu::BufferWriteMapping,
+    vertex_staging: StagingRing,
     index_buffer: wgpu::Buffer,
-    index_buffer_write_mapping: wgpu::BufferWriteMapping,
+    index_staging: StagingRing,
 
-    max_vertices: u32,
-    max_indices: u32,
+    index_format: wgpu::IndexFormat,
+    vertex_capacity: u32,
+    index_capacity: u32,
+    max_vertices: Option<u32>,
+    max_indices: Option<u32>,
     num_vertices: u32,
     num_indices: u32,
     
-    loaded_textures: HashMap<String, Box<Texture>>,
-    draw_call_textures: [Option<String>; MAX_TEXTURES_PER_DRAW_CALL],
+    // Maps texture names to the part of texture_array they occupy
+    loaded_textures: HashMap<String, TextureRegion>,
+    texture_array: TextureArray,
+    texture_atlas: TextureAtlas,
+    // Samplers picked for textures with set_texture_sampler, other textures keep the sampler of their vertices
+    texture_samplers: HashMap<String, Sampler>,
+    // Replaces the sampler of everything drawn while it is set
+    sampler_override: Option<Sampler>,
+    // Their names are also in loaded_textures
+    render_targets: HashMap<String, RenderTarget>,
+    // The target render_to_target is drawing into
+    current_target: Option<String>,
+    // Targets used by the passes since the last frame ended, they can't be rendered again until it ends
+    sampled_targets: HashSet<String>,
     
     texture_sampler_bind_group: wgpu::BindGroup,
     texture_bind_group_layout: wgpu::BindGroupLayout,
-
-    uniforms: Uniforms,
-    uniform_buffer: wgpu::Buffer,
+    texture_bind_group: wgpu::BindGroup,
+
+    // The cameras set with update during the frame, the last one is used by the following draws
+    // Every camera has a slot in the uniform buffer
+    cameras: Vec<Uniforms>,
+    // Whether a queued draw uses the last camera, otherwise update can replace it
+    last_camera_used: bool,
+    uniform_buffer: StreamedBuffer,
+    // Draw transforms of the current batch, indexed by Vertex::transform_id
+    transform_buffer: StreamedBuffer,
+    // Has to be recreated whenever the uniform or transform buffer grows
     uniform_bind_group: wgpu::BindGroup,
+    // Material uniforms set since the last batch was rendered, copied into the material's buffer by render
+    material_staging: StagingRing,
+    material_staging_used: usize,
+    material_uploads: Vec<(usize, Range<usize>)>,
+
+    meshes: MeshStore<Mesh>,
+    batch_items: Vec<BatchItem>,
+    // Instances used by the meshes in the current batch
+    instance_buffer: StreamedBuffer,
+    batch_instances: Vec<Instance>,
+    // Transform 0 is the identity used by untransformed shapes
+    batch_transforms: Vec<[[f32; 4]; 3]>,
+
+    // Draws are queued until end_render, which sorts them by layer
+    draw_queue: Vec<QueuedDraw>,
+    // State used by the following draws
+    layer: i32,
+    blend_mode: BlendMode,
+    material: usize,
+    viewport: Option<Viewport>,
 
     nr_draws_this_frame: u32,
     frame: Option<DrawableFrame>,
 
+    // Stats of the frame being drawn, draws queued before begin_render count towards it
+    frame_stats: FrameStats,
+    frame_texture_layers: HashSet<u32>,
+    last_frame_stats: FrameStats,
+    stats_aggregator: StatsAggregator,
+
+    post_bind_group_layout: wgpu::BindGroupLayout,
+    post_sampler: wgpu::Sampler,
+    post_passes: Vec<PostPass>,
+    // One set per frame size like frame_attachments, the most recently used one is last
+    post_targets: Vec<PostTargets>,
+    // The frame passed to begin_render while post effects are on, the last pass renders into it
+    post_frame: Option<DrawableFrame>,
+
     clear_color: wgpu::Color,
 }
 
 impl Renderer {
-    pub async fn new(max_vertices: u32, max_indices: u32, format: wgpu::TextureFormat, compatible_surface: Option<&wgpu::Surface>) -> Self {
+    // The adapter is picked for compatible_surface, other windows can share the renderer if their surfaces work with it too
+    // Any window's frame can be passed to begin_render, each window keeps its own swap chain
+    pub async fn new(desc: &RendererDescriptor, compatible_surface: Option<&wgpu::Surface>) -> Result<Self, RendererError> {
+        let format = desc.format;
+        let index_format = desc.index_format;
+        let sample_count = desc.sample_count;
+        if !matches!(sample_count, 1 | 2 | 4 | 8) {
+            return Err(RendererError::InvalidDescriptor(format!("sample_count has to be 1, 2, 4 or 8, not {}", sample_count)));
+        }
+        let max_vertices = desc.max_vertices;
+        let max_indices = desc.max_indices;
+        // A buffer has to be able to hold at least one triangle
+        let vertex_capacity = desc.initial_vertices.min(max_vertices.unwrap_or(u32::MAX)).max(3);
+        let index_capacity = desc.initial_indices.min(max_indices.unwrap_or(u32::MAX)).max(3);
+        if max_vertices.unwrap_or(3) < 3 || max_indices.unwrap_or(3) < 3 {
+            return Err(RendererError::InvalidDescriptor(String::from("the renderer's buffers must be able to hold at least one triangle")));
+        }
+
         let adapter = wgpu::Adapter::request(
             &wgpu::RequestAdapterOptions {
                 power_preference: wgpu::PowerPreference::Default,
                 compatible_surface,
             },
             wgpu::BackendBit::PRIMARY,
-        ).await.unwrap();
+        ).await.ok_or(RendererError::NoAdapter)?;
 
         let (device, queue) = adapter.request_device(
             &wgpu::DeviceDescriptor {
@@ -128,12 +690,13 @@ impl Renderer {
             }
         ).await;
 
+        // The placeholder is always layer 0
+        let mut texture_array = TextureArray::new(&device, desc.format, desc.texture_layer_size, 4);
         let placeholder_texture_bytes = include_bytes!("textures/placeholder_texture.png");
-        let (placeholder_texture, cmd_buffer) = Texture::from_bytes(
+        let (placeholder_texture_region, cmd_buffer) = texture_array.add_layer_from_bytes(
             &device,
             placeholder_texture_bytes,
-            PLACEHOLDER_TEXTURE_NAME,
-        ).unwrap();
+        )?;
 
         queue.submit(&[cmd_buffer]);
 
@@ -212,62 +775,56 @@ impl Renderer {
             }
         );
 
-        macro_rules! create_tex_desc {
-            ($binding: expr) => {
-                wgpu::BindGroupLayoutEntry {
-                    binding: $binding,
-                    visibility: wgpu::ShaderStage::FRAGMENT,
-                    ty: wgpu::BindingType::SampledTexture {
-                        multisampled: false,
-                        dimension: wgpu::TextureViewDimension::D2,
-                        component_type: wgpu::TextureComponentType::Uint,
-                    },
-                }
-            }
-        }
-
         let texture_bind_group_layout = device.create_bind_group_layout(
             &wgpu::BindGroupLayoutDescriptor {
                 bindings: &[
-                    create_tex_desc!(0),
-                    create_tex_desc!(1),
-                    create_tex_desc!(2),
-                    create_tex_desc!(3),
-                    create_tex_desc!(4),
-                    create_tex_desc!(5),
-                    create_tex_desc!(6),
-                    create_tex_desc!(7),
-                    create_tex_desc!(8),
-                    create_tex_desc!(9),
-                    ],
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::SampledTexture {
+                            multisampled: false,
+                            dimension: wgpu::TextureViewDimension::D2Array,
+                            component_type: wgpu::TextureComponentType::Float,
+                        },
+                    },
+                ],
                 label: Some("texture_bind_group_layout"),
             }
         );
-        // Texture bind group will be created each frame
+        // Texture bind group has to be recreated whenever the texture array grows
+        let texture_bind_group = create_texture_bind_group(&device, &texture_bind_group_layout, &texture_array);
 
         let mut loaded_textures = HashMap::new();
-        loaded_textures.insert(String::from(PLACEHOLDER_TEXTURE_NAME), placeholder_texture);
-
-        let draw_call_textures = [None, None, None, None, None, None, None, None, None, None];
-
-        let vs_module = shader::create_vertex_shader("src/shaders/shader.vs", &device);
-        let fs_module = shader::create_fragment_shader("src/shaders/shader.fs", &device);
-
-        let uniforms = Uniforms::new();
+        loaded_textures.insert(String::from(PLACEHOLDER_TEXTURE_NAME), placeholder_texture_region);
+
+        let (vertex_shader, fragment_shader) = match &desc.shader_directory {
+            Some(directory) => (
+                ShaderSource::File(format!("{}/shader.vs", directory)),
+                ShaderSource::File(format!("{}/shader.fs", directory)),
+            ),
+            None => (ShaderSource::Spirv(DEFAULT_VERTEX_SPIRV), ShaderSource::Spirv(DEFAULT_FRAGMENT_SPIRV)),
+        };
+        let (vs_module, fs_module) = shader::create_shaders(&vertex_shader, &fragment_shader, &device)?;
 
-        let uniform_buffer = device.create_buffer_with_data(
-            bytemuck::cast_slice(&[uniforms]),
-            wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
-        );
+        let uniform_buffer = StreamedBuffer::new(&device, "Uniform buffer", wgpu::BufferUsage::UNIFORM, INITIAL_CAMERA_CAPACITY as u64 * UNIFORM_SLOT_SIZE)?;
 
         let uniform_bind_group_layout = device.create_bind_group_layout(
             &wgpu::BindGroupLayoutDescriptor {
                 bindings: &[
+                    // The offset picks the camera of the batch
                     wgpu::BindGroupLayoutEntry {
                         binding: 0,
                         visibility: wgpu::ShaderStage::VERTEX,
                         ty: wgpu::BindingType::UniformBuffer {
+                            dynamic: true,
+                        },
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStage::VERTEX,
+                        ty: wgpu::BindingType::StorageBuffer {
                             dynamic: false,
+                            readonly: true,
                         },
                     },
                 ],
@@ -275,177 +832,318 @@ impl Renderer {
             }
         );
 
-        let uniform_bind_group = device.create_bind_group(
-            &wgpu::BindGroupDescriptor {
-                layout: &uniform_bind_group_layout,
+        let transform_buffer = StreamedBuffer::new(
+            &device,
+            "Transform buffer",
+            wgpu::BufferUsage::STORAGE_READ,
+            std::mem::size_of::<[[f32; 4]; 3]>() as u64 * INITIAL_TRANSFORM_CAPACITY as u64,
+        )?;
+        let uniform_bind_group = create_uniform_bind_group(&device, &uniform_bind_group_layout, &uniform_buffer, &transform_buffer);
+        let material_staging = StagingRing::new(&device, "Material staging buffer", INITIAL_MATERIAL_STAGING_SIZE)?;
+        let instance_buffer = StreamedBuffer::new(
+            &device,
+            "Instance buffer",
+            wgpu::BufferUsage::VERTEX,
+            std::mem::size_of::<Instance>() as u64 * INITIAL_INSTANCE_CAPACITY as u64,
+        )?;
+        
+        // Custom uniforms of materials
+        let material_bind_group_layout = device.create_bind_group_layout(
+            &wgpu::BindGroupLayoutDescriptor {
                 bindings: &[
-                    wgpu::Binding {
+                    wgpu::BindGroupLayoutEntry {
                         binding: 0,
-                        resource: wgpu::BindingResource::Buffer {
-                            buffer: &uniform_buffer,
-                            range: 0..std::mem::size_of_val(&uniforms) as wgpu::BufferAddress,
+                        visibility: wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::UniformBuffer {
+                            dynamic: false,
                         },
                     },
                 ],
-                label: Some("uniform_bind_group"),
-            },
+                label: Some("material_bind_group_layout"),
+            }
         );
-        
+
+        // Inputs of post effect passes, see post_vignette.fs
+        let post_bind_group_layout = device.create_bind_group_layout(
+            &wgpu::BindGroupLayoutDescriptor {
+                bindings: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler {
+                            comparison: false,
+                        },
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::SampledTexture {
+                            multisampled: false,
+                            dimension: wgpu::TextureViewDimension::D2,
+                            component_type: wgpu::TextureComponentType::Float,
+                        },
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::SampledTexture {
+                            multisampled: false,
+                            dimension: wgpu::TextureViewDimension::D2,
+                            component_type: wgpu::TextureComponentType::Float,
+                        },
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::UniformBuffer {
+                            dynamic: false,
+                        },
+                    },
+                ],
+                label: Some("post_bind_group_layout"),
+            }
+        );
+        let post_sampler = device.create_sampler(create_tex_sampler_desc!(wgpu::AddressMode::ClampToEdge, wgpu::FilterMode::Linear));
+
         let render_pipeline_layout = device.create_pipeline_layout(
             &wgpu::PipelineLayoutDescriptor {
                 bind_group_layouts: &[&uniform_bind_group_layout, &texture_sampler_bind_group_layout, &texture_bind_group_layout],
             }
         );
 
-        let render_pipeline = device.create_render_pipeline(
-            &wgpu::RenderPipelineDescriptor {
-                layout: &render_pipeline_layout,
-                vertex_stage: wgpu::ProgrammableStageDescriptor {
-                    module: &vs_module,
-                    entry_point: "main",
-                },
-                fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
-                    module: &fs_module,
-                    entry_point: "main",
-                }),
-                rasterization_state: Some(wgpu::RasterizationStateDescriptor {
-                    front_face: wgpu::FrontFace::Ccw,
-                    cull_mode: wgpu::CullMode::None,
-                    depth_bias: 0,
-                    depth_bias_slope_scale: 0.0,
-                    depth_bias_clamp: 0.0,
-                }),
-                primitive_topology: wgpu::PrimitiveTopology::TriangleList,
-                color_states: &[
-                    wgpu::ColorStateDescriptor {
-                        format: format,//sc_desc.format,
-                        color_blend: wgpu::BlendDescriptor {
-                            src_factor: wgpu::BlendFactor::SrcAlpha,
-                            dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
-                            operation: wgpu::BlendOperation::Add,
-                        },
-                        alpha_blend: wgpu::BlendDescriptor::REPLACE,
-                        write_mask: wgpu::ColorWrite::ALL,
-                    },
-                ],
-                depth_stencil_state: None,
-                vertex_state: wgpu::VertexStateDescriptor {
-                    index_format: wgpu::IndexFormat::Uint16,
-                    vertex_buffers: &[
-                        Vertex::desc(),
-                    ],
-                },
-                sample_count: 1,
-                sample_mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
+        let pipeline_settings = PipelineSettings {
+            layout: render_pipeline_layout,
+            vs_module,
+            fs_module,
+            format,
+            index_format,
+            sample_count,
+        };
+
+        // Pipelines for the other blend modes are created when they are first used
+        let mut render_pipelines = HashMap::new();
+        render_pipelines.insert(
+            BlendMode::Normal,
+            create_render_pipeline(&device, &pipeline_settings, BlendMode::Normal),
         );
+        let mask_pipeline = create_mask_pipeline(&device, &pipeline_settings);
+
+        let default_material = Material {
+            shaders_modified: shader::get_modified_time(&[&vertex_shader, &fragment_shader]),
+            vertex_shader,
+            fragment_shader,
+            settings: pipeline_settings,
+            pipelines: render_pipelines,
+            uniforms: None,
+        };
+        let mut material_ids = HashMap::new();
+        material_ids.insert(String::from(DEFAULT_MATERIAL_NAME), DEFAULT_MATERIAL);
 
         // let vertex_buffer = device.create_buffer_with_data(
         //     bytemuck::cast_slice(VERTICES),
         //     wgpu::BufferUsage::VERTEX,
         // );
 
-        let vertex_buffer = device.create_buffer(
-            &wgpu::BufferDescriptor {
-                label: Some("Vertex buffer"),
-                size: (std::mem::size_of::<Vertex>() as u64 * max_vertices as u64) as wgpu::BufferAddress,
-                usage: wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::MAP_WRITE,
-            }
-        );
-        let future_vertex_write_mapping = vertex_buffer.map_write(
-            0,
-            (std::mem::size_of::<Vertex>() as u64 * max_vertices as u64) as wgpu::BufferAddress,
-        );
+        let vertex_buffer_size = (std::mem::size_of::<Vertex>() as u64 * vertex_capacity as u64) as wgpu::BufferAddress;
+        let vertex_buffer = create_target_buffer(&device, "Vertex buffer", wgpu::BufferUsage::VERTEX, vertex_buffer_size);
+        let vertex_staging = StagingRing::new(&device, "Vertex staging buffer", vertex_buffer_size)?;
         
         // let index_buffer = device.create_buffer_with_data(
         //     bytemuck::cast_slice(INDICES),
         //     wgpu::BufferUsage::INDEX,
         // );
             
-        let index_buffer = device.create_buffer(
-            &wgpu::BufferDescriptor {
-                label: Some("Index buffer"),
-                size: (std::mem::size_of::<u16>() as u64 * max_indices as u64) as wgpu::BufferAddress,
-                usage: wgpu::BufferUsage::INDEX | wgpu::BufferUsage::MAP_WRITE,
-            }
-        );
-        let future_index_write_mapping = index_buffer.map_write(
-            0,
-            (std::mem::size_of::<u16>() as u64 * max_indices as u64) as wgpu::BufferAddress,
-        );
-        
-        device.poll(wgpu::Maintain::Wait);
-        let vertex_buffer_write_mapping = future_vertex_write_mapping.await.unwrap();
-        let index_buffer_write_mapping = future_index_write_mapping.await.unwrap();
+        let index_buffer_size = (index_format_size(index_format) * index_capacity as u64) as wgpu::BufferAddress;
+        let index_buffer = create_target_buffer(&device, "Index buffer", wgpu::BufferUsage::INDEX, index_buffer_size);
+        let index_staging = StagingRing::new(&device, "Index staging buffer", index_buffer_size)?;
             
-        Self {
+        Ok(Self {
             device,
             queue,
 
-            render_pipeline,
+            format,
+            sample_count,
+            watch_shaders: desc.watch_shaders,
+            shader_errors: vec![],
+
+            materials: vec![default_material],
+            material_ids,
+            uniform_bind_group_layout,
+            texture_sampler_bind_group_layout,
+            material_bind_group_layout,
+            batch_blend_mode: BlendMode::Normal,
+            batch_material: DEFAULT_MATERIAL,
+            mask_pipeline,
+            frame_attachments: vec![],
+
+            clip_stack: ClipStack::new(),
+            clip_masks: vec![],
+            batch_clip: 0,
+            batch_camera: 0,
+            batch_viewport: None,
+            stencil_masks: vec![],
+            mask_draws: vec![],
+            clear_stencil: false,
 
             vertex_buffer,
-            vertex_buffer_write_mapping,
+            vertex_staging,
             index_buffer,
-            index_buffer_write_mapping,
+            index_staging,
 
+            index_format,
+            vertex_capacity,
+            index_capacity,
             max_vertices,
             max_indices,
             num_vertices: 0,
             num_indices: 0,
 
             loaded_textures,
-            draw_call_textures,
+            texture_array,
+            texture_atlas: TextureAtlas::new(),
+            texture_samplers: HashMap::new(),
+            sampler_override: None,
+            render_targets: HashMap::new(),
+            current_target: None,
+            sampled_targets: HashSet::new(),
 
             texture_sampler_bind_group,
             texture_bind_group_layout,
+            texture_bind_group,
 
-            uniforms,
+            cameras: vec![Uniforms::new()],
+            last_camera_used: false,
             uniform_buffer,
+            transform_buffer,
             uniform_bind_group,
+            material_staging,
+            material_staging_used: 0,
+            material_uploads: vec![],
+
+            meshes: MeshStore::new(),
+            batch_items: vec![],
+            instance_buffer,
+            batch_instances: vec![Instance::new(None)],
+            batch_transforms: vec![padded_columns(&Matrix3::identity())],
+
+            draw_queue: vec![],
+            layer: 0,
+            blend_mode: BlendMode::Normal,
+            material: DEFAULT_MATERIAL,
+            viewport: None,
 
             nr_draws_this_frame: 0,
             frame: None,
 
+            frame_stats: FrameStats::default(),
+            frame_texture_layers: HashSet::new(),
+            last_frame_stats: FrameStats::default(),
+            stats_aggregator: StatsAggregator::new(0),
+
+            post_bind_group_layout,
+            post_sampler,
+            post_passes: vec![],
+            post_targets: vec![],
+            post_frame: None,
+
             clear_color: wgpu::Color{r:0.1, g:0.2, b:0.3, a:0.0},
-        }
+        })
     }
 
     pub fn get_device(&self) -> &wgpu::Device {
         &self.device
     }
 
+    // Draws queued from now on use the camera, the ones queued before keep theirs
+    // Nothing is sent to the gpu until the draws are rendered
     pub fn update(&mut self, camera: &Camera) {
         self.device.poll(wgpu::Maintain::Poll);
 
-        self.uniforms.set_camera(camera);
-
-        let mut encoder = self.device.create_command_encoder(
-            &wgpu::CommandEncoderDescriptor {
-                label: Some("update encoder"),
-            },
-        );
-        let staging_buffer = self.device.create_buffer_with_data(
-            bytemuck::cast_slice(&[self.uniforms]),
-            wgpu::BufferUsage::COPY_SRC,
-        );
-        encoder.copy_buffer_to_buffer(&staging_buffer, 0, &self.uniform_buffer, 0, std::mem::size_of::<Uniforms>() as wgpu::BufferAddress);
-        self.queue.submit(&[encoder.finish()]);
+        let mut uniforms = Uniforms::new();
+        uniforms.set_camera(camera);
+        if self.last_camera_used {
+            self.cameras.push(uniforms);
+            self.last_camera_used = false;
+        } else {
+            // Always has at least one camera
+            *self.cameras.last_mut().unwrap() = uniforms;
+        }
     }
 
-    pub fn begin_render(&mut self, frame: DrawableFrame) {
-        match self.frame {
-            Some(_) => panic!("previous render has not ended"),
-            None => self.frame = Some(frame),
+    pub fn begin_render(&mut self, frame: DrawableFrame) -> Result<(), RendererError> {
+        if self.frame.is_some() {
+            return Err(RendererError::RenderInProgress);
         }
+        // With post effects everything is drawn into a post target, the passes end in the frame
+        let frame = if self.post_passes.is_empty() {
+            frame
+        } else {
+            let scene_view = self.prepare_post_targets(frame.width, frame.height).textures[0].create_default_view();
+            let scene_frame = DrawableFrame::from_texture_view(Box::new(scene_view), frame.width, frame.height);
+            self.post_frame = Some(frame);
+            scene_frame
+        };
+        self.frame = Some(frame);
         self.nr_draws_this_frame = 0;
+
+        if self.watch_shaders {
+            let shader_errors = self.reload_changed_shaders();
+            self.shader_errors.extend(shader_errors);
+        }
+        Ok(())
     }
 
-    fn render(&mut self) {
-        let frame = match &self.frame {
-            Some(x) => x.get_frame(),
-            None => panic!(),
+    // Recompiles the materials whose shader files changed since they were last compiled
+    // If a shader doesn't compile the material keeps its old pipelines, the compile errors are returned
+    pub fn reload_changed_shaders(&mut self) -> Vec<RendererError> {
+        let mut errors = vec![];
+        for id in 0..self.materials.len() {
+            let material = &mut self.materials[id];
+            let modified = shader::get_modified_time(&[&material.vertex_shader, &material.fragment_shader]);
+            if modified == material.shaders_modified {
+                continue;
+            }
+            // Failed compiles aren't retried until the files change again
+            material.shaders_modified = modified;
+
+            let (vs_module, fs_module) = match shader::create_shaders(&material.vertex_shader, &material.fragment_shader, &self.device) {
+                Ok(modules) => modules,
+                Err(error) => {
+                    errors.push(error);
+                    continue;
+                },
+            };
+            material.settings.vs_module = vs_module;
+            material.settings.fs_module = fs_module;
+
+            // Pipelines are rebuilt for the blend modes that were already used so the current batch state keeps working
+            let settings = &material.settings;
+            let device = &self.device;
+            material.pipelines = material.pipelines.keys()
+                .map(|blend_mode| (*blend_mode, create_render_pipeline(device, settings, *blend_mode)))
+                .collect();
+            if id == DEFAULT_MATERIAL {
+                self.mask_pipeline = create_mask_pipeline(&self.device, &self.materials[id].settings);
+            }
+        }
+        errors
+    }
+
+    fn render(&mut self, reason: FlushReason) -> Result<(), RendererError> {
+        let start = Instant::now();
+        self.frame_stats.add_flush(reason);
+        let frame_attachments = self.prepare_frame_attachments()?;
+        let stencil_view = frame_attachments.stencil.create_default_view();
+        let multisampled_color_view = frame_attachments.multisampled_color.as_ref().map(|texture| texture.create_default_view());
+
+        let (frame, frame_width, frame_height) = match &self.frame {
+            Some(x) => (x.get_frame(), x.width, x.height),
+            None => return Err(RendererError::NoRenderInProgress),
+        };
+        // With multisampling everything is drawn into the multisampled texture and resolved into the frame
+        let (color_attachment, resolve_target) = match &multisampled_color_view {
+            Some(view) => (view, Some(frame)),
+            None => (frame, None),
         };
 
         let mut encoder = self.device.create_command_encoder(
@@ -454,49 +1152,11 @@ impl Renderer {
             }
         );
 
-        self.vertex_buffer.unmap();
-        self.index_buffer.unmap();
-
-        macro_rules! create_texture_bind_group_binding {
-            ($binding:expr) => {{
-                let texture = match &self.draw_call_textures[$binding] {
-                    Some(name) => {
-                        match self.loaded_textures.get(name) {
-                            Some(tex) => tex,
-                            None => self.loaded_textures.get(PLACEHOLDER_TEXTURE_NAME).unwrap()
-                        }
-                    }
-                    None => {
-                        self.loaded_textures.get(PLACEHOLDER_TEXTURE_NAME).unwrap()
-                    }
-                };
-                wgpu::Binding {
-                    binding: $binding,
-                    resource: wgpu::BindingResource::TextureView(&texture.view),
-                }
-            }}
-        }
-
-        let texture_bind_group = self.device.create_bind_group(
-            &wgpu::BindGroupDescriptor {
-                layout: &self.texture_bind_group_layout,
-                bindings: &[
-                    create_texture_bind_group_binding!(0),
-                    create_texture_bind_group_binding!(1),
-                    create_texture_bind_group_binding!(2),
-                    create_texture_bind_group_binding!(3),
-                    create_texture_bind_group_binding!(4),
-                    create_texture_bind_group_binding!(5),
-                    create_texture_bind_group_binding!(6),
-                    create_texture_bind_group_binding!(7),
-                    create_texture_bind_group_binding!(8),
-                    create_texture_bind_group_binding!(9),
-                ],
-                label: Some("texture_bind_group"),
-            },
-        );
-        // println!("{:?}", self.draw_call_textures);
-        self.draw_call_textures = [None, None, None, None, None, None, None, None, None, None];
+        // The gpu copies the batch out of the staging buffers while the next batch is written into the next ones
+        let vertex_bytes = self.num_vertices as usize * std::mem::size_of::<Vertex>();
+        self.vertex_staging.copy_to(&mut encoder, 0..vertex_bytes, &self.vertex_buffer, 0);
+        let index_bytes = self.num_indices as usize * index_format_size(self.index_format) as usize;
+        self.index_staging.copy_to(&mut encoder, 0..index_bytes, &self.index_buffer, 0);
 
         let operation = if self.nr_draws_this_frame == 0 {
             wgpu::LoadOp::Clear
@@ -504,179 +1164,1725 @@ impl Renderer {
             wgpu::LoadOp::Load
         };
 
+        let stencil_operation = if self.nr_draws_this_frame == 0 || self.clear_stencil {
+            wgpu::LoadOp::Clear
+        } else {
+            wgpu::LoadOp::Load
+        };
+
+        // Every camera of the frame is written so earlier batches' slots stay valid if the buffer grows
+        let cameras = &self.cameras;
+        let uniforms_size = cameras.len() * UNIFORM_SLOT_SIZE as usize;
+        let mut buffers_replaced = self.uniform_buffer.write(&self.device, &mut encoder, uniforms_size, |bytes| {
+            for (slot, uniforms) in bytes.chunks_exact_mut(UNIFORM_SLOT_SIZE as usize).zip(cameras) {
+                slot[..std::mem::size_of::<Uniforms>()].copy_from_slice(bytemuck::bytes_of(uniforms));
+            }
+        })?;
+        let transforms = bytemuck::cast_slice(&self.batch_transforms);
+        buffers_replaced |= self.transform_buffer.write(&self.device, &mut encoder, transforms.len(), |bytes| bytes.copy_from_slice(transforms))?;
+        if buffers_replaced {
+            self.uniform_bind_group = create_uniform_bind_group(&self.device, &self.uniform_bind_group_layout, &self.uniform_buffer, &self.transform_buffer);
+        }
+        // Instance 0 is the identity used by the batched shapes
+        let instances = bytemuck::cast_slice(&self.batch_instances);
+        self.instance_buffer.write(&self.device, &mut encoder, instances.len(), |bytes| bytes.copy_from_slice(instances))?;
+
+        for (material, bytes) in self.material_uploads.drain(..) {
+            if let Some(uniforms) = &self.materials[material].uniforms {
+                self.material_staging.copy_to(&mut encoder, bytes, &uniforms.buffer, 0);
+            }
+        }
+        self.material_staging_used = 0;
+
         let mut render_pass = encoder.begin_render_pass(
             &wgpu::RenderPassDescriptor {
                 color_attachments: &[
                     wgpu::RenderPassColorAttachmentDescriptor {
-                        attachment: frame,
-                        resolve_target: None,
+                        attachment: color_attachment,
+                        resolve_target,
                         load_op: operation,
                         store_op: wgpu::StoreOp::Store,
                         clear_color: self.clear_color,
                     },
                 ],
-                depth_stencil_attachment: None,
+                depth_stencil_attachment: Some(
+                    wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                        attachment: &stencil_view,
+                        depth_load_op: wgpu::LoadOp::Clear,
+                        depth_store_op: wgpu::StoreOp::Store,
+                        clear_depth: 1.0,
+                        stencil_load_op: stencil_operation,
+                        stencil_store_op: wgpu::StoreOp::Store,
+                        clear_stencil: 0,
+                    },
+                ),
             },
         );
 
-        render_pass.set_pipeline(&self.render_pipeline);
-        render_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
         render_pass.set_bind_group(1, &self.texture_sampler_bind_group, &[]);
-        render_pass.set_bind_group(2, &texture_bind_group, &[]);
+        render_pass.set_bind_group(2, &self.texture_bind_group, &[]);
         
         render_pass.set_vertex_buffer(0, &self.vertex_buffer, 0, 0);
+        render_pass.set_vertex_buffer(1, self.instance_buffer.get_buffer(), 0, 0);
         render_pass.set_index_buffer(&self.index_buffer, 0, 0);
-        render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
-        self.num_vertices = 0;
-        self.num_indices = 0;
 
-        drop(render_pass);
-
-        self.queue.submit(
-            &[encoder.finish()]
-        );
-
-        let future_vertex_write_mapping = self.vertex_buffer.map_write(
-            0,
-            (std::mem::size_of::<Vertex>() as u64 * self.max_vertices as u64) as wgpu::BufferAddress,
-        );
+        // Every mask increments the stencil value of the pixels that are inside all of the masks before it
+        // Masks are drawn with the camera and viewport they were pushed with, the batch's shapes with its own
+        let frame_viewport = Viewport { x: 0, y: 0, width: frame_width, height: frame_height };
+        render_pass.set_pipeline(&self.mask_pipeline);
+        for mask_draw in &self.mask_draws {
+            let camera_offset = (mask_draw.camera as wgpu::BufferAddress * UNIFORM_SLOT_SIZE) as wgpu::DynamicOffset;
+            render_pass.set_bind_group(0, &self.uniform_bind_group, &[camera_offset]);
+            let viewport = mask_draw.viewport.unwrap_or(frame_viewport);
+            render_pass.set_viewport(viewport.x as f32, viewport.y as f32, viewport.width as f32, viewport.height as f32, 0.0, 1.0);
+            render_pass.set_stencil_reference(mask_draw.stencil_value);
+            render_pass.draw_indexed(mask_draw.indices.clone(), 0, 0..1);
+        }
 
-        let future_index_write_mapping = self.index_buffer.map_write(
-            0,
-            (std::mem::size_of::<u16>() as u64 * self.max_indices as u64) as wgpu::BufferAddress,
+        let camera_offset = (self.batch_camera as wgpu::BufferAddress * UNIFORM_SLOT_SIZE) as wgpu::DynamicOffset;
+        render_pass.set_bind_group(0, &self.uniform_bind_group, &[camera_offset]);
+        let viewport = self.batch_viewport.unwrap_or(frame_viewport);
+        render_pass.set_viewport(viewport.x as f32, viewport.y as f32, viewport.width as f32, viewport.height as f32, 0.0, 1.0);
+        let viewport_rect = viewport.to_scissor_rect().intersect(frame_viewport.to_scissor_rect());
+
+        // So pixels inside all masks have a stencil value equal to the number of masks
+        let clip = self.clip_stack.get(self.batch_clip);
+        let scissor = match clip.scissor {
+            Some(scissor) => scissor.intersect(viewport_rect),
+            None => viewport_rect,
+        };
+        if scissor.width > 0 && scissor.height > 0 {
+            let material = &self.materials[self.batch_material];
+            render_pass.set_pipeline(&material.pipelines[&self.batch_blend_mode]);
+            if let Some(uniforms) = &material.uniforms {
+                render_pass.set_bind_group(3, &uniforms.bind_group, &[]);
+            }
+            render_pass.set_stencil_reference(clip.masks.len() as u32);
+            render_pass.set_scissor_rect(scissor.x, scissor.y, scissor.width, scissor.height);
+            for item in &self.batch_items {
+                match item {
+                    BatchItem::Shapes { indices, instances } => {
+                        render_pass.set_vertex_buffer(0, &self.vertex_buffer, 0, 0);
+                        render_pass.set_index_buffer(&self.index_buffer, 0, 0);
+                        render_pass.draw_indexed(indices.clone(), 0, instances.clone());
+                    },
+                    BatchItem::Mesh { mesh, instance } => {
+                        if let Some(mesh) = self.meshes.get(*mesh) {
+                            for part in &mesh.parts {
+                                render_pass.set_vertex_buffer(0, &part.vertex_buffer, 0, 0);
+                                render_pass.set_index_buffer(&part.index_buffer, 0, 0);
+                                render_pass.draw_indexed(0..part.num_indices, 0, *instance..*instance+1);
+                            }
+                        }
+                    },
+                }
+            }
+        }
+        self.num_vertices = 0;
+        self.num_indices = 0;
+        self.mask_draws.clear();
+        self.batch_items.clear();
+        self.batch_instances.truncate(1);
+        self.batch_transforms.truncate(1);
+        self.clear_stencil = false;
+
+        drop(render_pass);
+
+        self.queue.submit(
+            &[encoder.finish()]
         );
-        
-        self.device.poll(wgpu::Maintain::Wait);
-        use futures::executor::block_on;
-        self.vertex_buffer_write_mapping = block_on(future_vertex_write_mapping).unwrap();
-        self.index_buffer_write_mapping = block_on(future_index_write_mapping).unwrap();
+
+        self.vertex_staging.advance(&self.device)?;
+        self.index_staging.advance(&self.device)?;
+        self.uniform_buffer.advance(&self.device)?;
+        self.transform_buffer.advance(&self.device)?;
+        self.instance_buffer.advance(&self.device)?;
+        self.material_staging.advance(&self.device)?;
 
         self.nr_draws_this_frame += 1;
+        self.frame_stats.render_time += start.elapsed();
+        Ok(())
     }
 
-    pub fn end_render(&mut self) {
-        self.render();
+    // The frame is finished even if rendering fails, so begin_render can be called again
+    pub fn end_render(&mut self) -> Result<(), RendererError> {
+        if self.frame.is_none() {
+            return Err(RendererError::NoRenderInProgress);
+        }
+        // Batches rendered while the queue is flushed count as render time, not draw time
+        let start = Instant::now();
+        let render_time = self.frame_stats.render_time;
+        let result = self.flush_draw_queue();
+        let flush_render_time = self.frame_stats.render_time - render_time;
+        self.frame_stats.draw_time += start.elapsed().checked_sub(flush_render_time).unwrap_or_default();
+
+        let result = result.and_then(|_| self.render(FlushReason::EndOfFrame));
+        self.finish_frame(result)
+    }
+
+    // Ends the frame with the result of rendering its draws, draws that weren't rendered are dropped
+    fn finish_frame(&mut self, result: Result<(), RendererError>) -> Result<(), RendererError> {
+        self.draw_queue.clear();
+        // A frame that failed before its first batch would show whatever its texture held before, so it is cleared
+        // A frame that failed later keeps what was rendered up to the error
+        // Either way it goes through the post effects like every other frame
+        if self.nr_draws_this_frame == 0 {
+            self.clear_frame();
+        }
+        if let Some(frame) = self.post_frame.take() {
+            let start = Instant::now();
+            self.apply_post_effects(&frame);
+            self.frame_stats.render_time += start.elapsed();
+        }
+        self.finish_frame_stats();
+
+        // Clips only last for one frame
+        self.clip_stack.reset();
+        self.clip_masks.clear();
+        self.batch_clip = 0;
+        self.stencil_masks.clear();
+        // The last camera stays in use for the next frame, viewports only last for one frame like clips
+        self.cameras.drain(..self.cameras.len() - 1);
+        self.last_camera_used = false;
+        self.batch_camera = 0;
+        self.viewport = None;
+        self.batch_viewport = None;
         self.frame = None;
+        // Passes of render_to_target belong to the next frame that isn't a target
+        if self.current_target.is_none() {
+            self.sampled_targets.clear();
+        }
+        result
     }
 
-    pub fn load_texture(&mut self, bytes:&[u8], label: String) -> bool {
-        let (texture, cmd_buffer) = Texture::from_bytes(&self.device, bytes, &label).unwrap();
+    // Creates a texture that can be rendered to without a window
+    // The target uses the same format as the renderer's pipeline
+    pub fn create_offscreen_target(&self, width: u32, height: u32) -> Result<OffscreenTarget, RendererError> {
+        if width == 0 || height == 0 {
+            return Err(RendererError::InvalidTargetSize { width, height });
+        }
+        Ok(OffscreenTarget::new(&self.device, width, height, self.format))
+    }
+
+    pub fn read_offscreen_target(&self, target: &OffscreenTarget) -> Result<image::RgbaImage, RendererError> {
+        Ok(target.read_pixels(&self.device, &self.queue)?)
+    }
+
+    // Renders everything drawn in draw_fn into a new offscreen target of the given size and returns the pixels
+    // Call update before this if the camera's aspect ratio needs to match the image
+    pub fn render_offscreen<F: FnOnce(&mut Self)>(&mut self, width: u32, height: u32, draw_fn: F) -> Result<image::RgbaImage, RendererError> {
+        let target = self.create_offscreen_target(width, height)?;
+        self.begin_render(target.create_frame())?;
+        draw_fn(self);
+        self.end_render()?;
+        self.read_offscreen_target(&target)
+    }
+
+    // Creates a target that render_to_target draws into, shapes use it as a texture through its name
+    // It takes up a layer of the texture array, so it can't be bigger than a layer; it starts out transparent
+    // Returns false if a texture with this name is already loaded
+    pub fn create_render_target(&mut self, name: &str, width: u32, height: u32) -> Result<bool, RendererError> {
+        if self.loaded_textures.contains_key(name) {
+            return Ok(false);
+        }
+        let layer_size = self.texture_array.get_layer_size();
+        if width == 0 || height == 0 || width > layer_size || height > layer_size {
+            return Err(RendererError::InvalidRenderTargetSize { width, height, layer_size });
+        }
+
+        let empty_image = image::DynamicImage::ImageRgba8(image::RgbaImage::new(width, height));
+        let (region, cmd_buffer) = self.texture_array.add_layer(&self.device, &empty_image)?;
         self.queue.submit(&[cmd_buffer]);
+        // The texture array might have been recreated with more layers
+        self.texture_bind_group = create_texture_bind_group(&self.device, &self.texture_bind_group_layout, &self.texture_array);
+
+        let texture = self.device.create_texture(
+            &wgpu::TextureDescriptor {
+                label: Some("render_target"),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth: 1,
+                },
+                array_layer_count: 1,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: self.format,
+                usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::COPY_SRC,
+            },
+        );
+        self.loaded_textures.insert(String::from(name), region);
+        self.render_targets.insert(String::from(name), RenderTarget {
+            texture,
+            width,
+            height,
+            layer: region.layer,
+            attachments: None,
+        });
+        Ok(true)
+    }
+
+    // Renders everything drawn in draw_fn into the target, like render_offscreen does with a new texture
+    // Targets are rendered outside of begin_render and end_render, before the frames whose shapes use them
+    // A target that is drawn into another one has to be rendered first; rendering happens in the order of the calls
+    // Rendering a target that an earlier pass of the same frame used fails, that pass would have seen the old contents
+    // The result is copied into the texture array at the end, so draws into a target see its previous contents
+    pub fn render_to_target<F: FnOnce(&mut Self)>(&mut self, name: &str, draw_fn: F) -> Result<(), RendererError> {
+        if self.frame.is_some() {
+            return Err(RendererError::RenderInProgress);
+        }
+        if self.sampled_targets.contains(name) {
+            return Err(RendererError::TargetSampledBeforeRender(String::from(name)));
+        }
+        let mut target = match self.render_targets.remove(name) {
+            Some(target) => target,
+            None => return Err(RendererError::UnknownRenderTarget(String::from(name))),
+        };
+
+        // Post effects are only applied to frames
+        let post_passes = std::mem::take(&mut self.post_passes);
+        let frame_attachments = std::mem::replace(&mut self.frame_attachments, target.attachments.take().into_iter().collect());
+        let frame = DrawableFrame::from_texture_view(Box::new(target.texture.create_default_view()), target.width, target.height);
+        // Draws queued outside of a frame wait for the next frame with their clips and viewport instead of ending up in the target
+        let queued_draws = std::mem::take(&mut self.draw_queue);
+        let clip_stack = std::mem::replace(&mut self.clip_stack, ClipStack::new());
+        let clip_masks = std::mem::take(&mut self.clip_masks);
+        let viewport = self.viewport.take();
+        let cameras = self.cameras.clone();
+        self.current_target = Some(String::from(name));
+        let result = self.begin_render(frame).and_then(|_| {
+            draw_fn(self);
+            self.end_render()
+        });
+        self.current_target = None;
+        target.attachments = std::mem::replace(&mut self.frame_attachments, frame_attachments).pop();
+        self.post_passes = post_passes;
+        // end_render only kept the last camera, the queued draws and masks still point to the cameras they were queued with
+        if !queued_draws.is_empty() || !clip_masks.is_empty() {
+            let last_camera = *self.cameras.last().unwrap();
+            self.cameras = cameras;
+            self.cameras.push(last_camera);
+            self.last_camera_used = false;
+        }
+        self.draw_queue = queued_draws;
+        self.clip_stack = clip_stack;
+        self.clip_masks = clip_masks;
+        self.viewport = viewport;
+
+        if result.is_ok() {
+            let mut encoder = self.device.create_command_encoder(
+                &wgpu::CommandEncoderDescriptor {
+                    label: Some("render target copy encoder"),
+                },
+            );
+            encoder.copy_texture_to_texture(
+                wgpu::TextureCopyView {
+                    texture: &target.texture,
+                    mip_level: 0,
+                    array_layer: 0,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                wgpu::TextureCopyView {
+                    texture: &self.texture_array.texture,
+                    mip_level: 0,
+                    array_layer: target.layer,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                wgpu::Extent3d {
+                    width: target.width,
+                    height: target.height,
+                    depth: 1,
+                },
+            );
+            self.queue.submit(&[encoder.finish()]);
+        }
+        self.render_targets.insert(String::from(name), target);
+        result
+    }
+
+    // Moves the stats of the frame end_render finished to last_frame_stats and adds them to the current group
+    fn finish_frame_stats(&mut self) {
+        let mut stats = std::mem::take(&mut self.frame_stats);
+        stats.frames = 1;
+        stats.textures = self.frame_texture_layers.len() as u32;
+        self.frame_texture_layers.clear();
+        self.stats_aggregator.add_frame(&stats);
+        self.last_frame_stats = stats;
+    }
+
+    // Stats of the last frame finished by end_render
+    // render_to_target and render_offscreen finish frames of their own
+    #[allow(dead_code)]
+    pub fn get_frame_stats(&self) -> &FrameStats {
+        &self.last_frame_stats
+    }
+
+    // Adds up the stats of every group of this many frames, 0 turns it off
+    // The frames already added up are dropped
+    pub fn set_stats_aggregation(&mut self, frames: u32) {
+        self.stats_aggregator = StatsAggregator::new(frames);
+    }
+
+    // Errors of shaders that were edited while watch_shaders is on, each error is only returned once
+    pub fn take_shader_errors(&mut self) -> Vec<RendererError> {
+        std::mem::take(&mut self.shader_errors)
+    }
+
+    // The stats of the last finished group of frames, each group is only returned once
+    pub fn take_aggregated_stats(&mut self) -> Option<FrameStats> {
+        self.stats_aggregator.take()
+    }
+
+    // Replaces the effects end_render applies to every frame, in order; an empty slice turns them off
+    // Render targets are not post processed
+    // If a shader doesn't compile the previous effects are kept
+    pub fn set_post_effects(&mut self, effects: &[PostEffect]) -> Result<(), RendererError> {
+        if self.frame.is_some() {
+            return Err(RendererError::RenderInProgress);
+        }
+        let vs_module = shader::create_vertex_shader(&ShaderSource::Spirv(POST_VERTEX_SPIRV), &self.device)?;
+        let mut post_passes = vec![];
+        for effect in effects {
+            let descriptors = effect.get_passes(|name| self.get_texture_region(name));
+            for (i, descriptor) in descriptors.iter().enumerate() {
+                let fs_module = shader::create_fragment_shader(&descriptor.fragment_shader, &self.device)?;
+                let uniforms = descriptor.uniform_data.as_ref().map(|data| create_material_uniforms(&self.device, &self.material_bind_group_layout, data));
+                let layout = match uniforms {
+                    Some(_) => self.device.create_pipeline_layout(
+                        &wgpu::PipelineLayoutDescriptor {
+                            bind_group_layouts: &[&self.post_bind_group_layout, &self.texture_bind_group_layout, &self.material_bind_group_layout],
+                        }
+                    ),
+                    None => self.device.create_pipeline_layout(
+                        &wgpu::PipelineLayoutDescriptor {
+                            bind_group_layouts: &[&self.post_bind_group_layout, &self.texture_bind_group_layout],
+                        }
+                    ),
+                };
+                post_passes.push(PostPass {
+                    pipeline: create_post_pipeline(&self.device, &layout, &vs_module, &fs_module, self.format),
+                    uniforms,
+                    starts_effect: i == 0,
+                });
+            }
+        }
+        self.post_passes = post_passes;
+        Ok(())
+    }
+
+    // Creates the textures the post effects ping-pong between if the frame's size changed
+    fn prepare_post_targets(&mut self, width: u32, height: u32) -> &PostTargets {
+        let existing = self.post_targets.iter().position(|post_targets| post_targets.width == width && post_targets.height == height);
+        if let Some(i) = existing {
+            let post_targets = self.post_targets.remove(i);
+            self.post_targets.push(post_targets);
+        } else {
+            let usage = wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED;
+            let create_texture = || create_attachment_texture(&self.device, "post_target", width, height, 1, self.format, usage);
+            let post_uniforms = [1.0 / width as f32, 1.0 / height as f32, 0.0, 0.0];
+            let post_targets = PostTargets {
+                textures: [create_texture(), create_texture(), create_texture()],
+                uniform_buffer: self.device.create_buffer_with_data(bytemuck::cast_slice(&post_uniforms), wgpu::BufferUsage::UNIFORM),
+                width,
+                height,
+            };
+            self.post_targets.push(post_targets);
+            if self.post_targets.len() > MAX_FRAME_ATTACHMENTS {
+                self.post_targets.remove(0);
+            }
+        }
+        // Never empty at this point
+        self.post_targets.last().unwrap()
+    }
+
+    // Fills the frame with the clear color without drawing anything
+    fn clear_frame(&self) {
+        let frame = match &self.frame {
+            Some(frame) => frame,
+            None => return,
+        };
+        let mut encoder = self.device.create_command_encoder(
+            &wgpu::CommandEncoderDescriptor {
+                label: Some("Clear encoder"),
+            }
+        );
+        encoder.begin_render_pass(
+            &wgpu::RenderPassDescriptor {
+                color_attachments: &[
+                    wgpu::RenderPassColorAttachmentDescriptor {
+                        attachment: frame.get_frame(),
+                        resolve_target: None,
+                        load_op: wgpu::LoadOp::Clear,
+                        store_op: wgpu::StoreOp::Store,
+                        clear_color: self.clear_color,
+                    },
+                ],
+                depth_stencil_attachment: None,
+            },
+        );
+        self.queue.submit(&[encoder.finish()]);
+    }
+
+    // The frame has been rendered into the first post target, every pass draws a full screen triangle into the next one
+    // begin_render prepared the targets for the frame, so they are the last ones
+    fn apply_post_effects(&self, frame: &DrawableFrame) {
+        let post_targets = match self.post_targets.last() {
+            Some(post_targets) => post_targets,
+            None => return,
+        };
+        let views: Vec<wgpu::TextureView> = post_targets.textures.iter().map(|texture| texture.create_default_view()).collect();
+
+        let mut encoder = self.device.create_command_encoder(
+            &wgpu::CommandEncoderDescriptor {
+                label: Some("Post effect encoder"),
+            }
+        );
+        let mut previous = 0;
+        let mut effect_input = 0;
+        for (i, pass) in self.post_passes.iter().enumerate() {
+            if pass.starts_effect {
+                effect_input = previous;
+            }
+            // The last pass renders into the frame, the others into a texture that isn't one of their inputs
+            let output = if i + 1 == self.post_passes.len() {
+                None
+            } else {
+                (0..views.len()).find(|texture| *texture != previous && *texture != effect_input)
+            };
+
+            let bind_group = self.device.create_bind_group(
+                &wgpu::BindGroupDescriptor {
+                    layout: &self.post_bind_group_layout,
+                    bindings: &[
+                        wgpu::Binding {
+                            binding: 0,
+                            resource: wgpu::BindingResource::Sampler(&self.post_sampler),
+                        },
+                        wgpu::Binding {
+                            binding: 1,
+                            resource: wgpu::BindingResource::TextureView(&views[previous]),
+                        },
+                        wgpu::Binding {
+                            binding: 2,
+                            resource: wgpu::BindingResource::TextureView(&views[effect_input]),
+                        },
+                        wgpu::Binding {
+                            binding: 3,
+                            resource: wgpu::BindingResource::Buffer {
+                                buffer: &post_targets.uniform_buffer,
+                                range: 0..std::mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                            },
+                        },
+                    ],
+                    label: Some("post_bind_group"),
+                },
+            );
+
+            let attachment = match output {
+                Some(texture) => &views[texture],
+                None => frame.get_frame(),
+            };
+            let mut render_pass = encoder.begin_render_pass(
+                &wgpu::RenderPassDescriptor {
+                    color_attachments: &[
+                        wgpu::RenderPassColorAttachmentDescriptor {
+                            attachment,
+                            resolve_target: None,
+                            load_op: wgpu::LoadOp::Clear,
+                            store_op: wgpu::StoreOp::Store,
+                            clear_color: wgpu::Color::TRANSPARENT,
+                        },
+                    ],
+                    depth_stencil_attachment: None,
+                },
+            );
+            render_pass.set_pipeline(&pass.pipeline);
+            render_pass.set_bind_group(0, &bind_group, &[]);
+            render_pass.set_bind_group(1, &self.texture_bind_group, &[]);
+            if let Some(uniforms) = &pass.uniforms {
+                render_pass.set_bind_group(2, &uniforms.bind_group, &[]);
+            }
+            render_pass.draw(0..3, 0..1);
+            drop(render_pass);
+
+            if let Some(texture) = output {
+                previous = texture;
+            }
+        }
+        self.queue.submit(&[encoder.finish()]);
+    }
 
+    // The texture gets a layer of its own at its own size, images bigger than a layer are rejected instead of being resized
+    // Repeating samplers wrap at the edge of the layer, so only textures as big as a layer repeat seamlessly
+    // Returns false if a texture with this label is already loaded
+    pub fn load_texture(&mut self, bytes:&[u8], label: String) -> Result<bool, RendererError> {
         let entry = self.loaded_textures.entry(label);
         match entry {
-            hash_map::Entry::Occupied(..) => false,
+            hash_map::Entry::Occupied(..) => Ok(false),
             hash_map::Entry::Vacant(..) => {
-                entry.or_insert(texture);
-                true
+                let (region, cmd_buffer) = self.texture_array.add_layer_from_bytes(&self.device, bytes)?;
+                self.queue.submit(&[cmd_buffer]);
+                entry.or_insert(region);
+                // The texture array might have been recreated with more layers
+                self.texture_bind_group = create_texture_bind_group(&self.device, &self.texture_bind_group_layout, &self.texture_array);
+                Ok(true)
+            },
+        }
+    }
+
+    // Packs the texture into a shared atlas page instead of giving it a layer of its own
+    // Shapes using it keep their texture coordinates in [0,1], draw maps them into the returned region
+    // Coordinates outside of [0,1] would sample neighbouring images, so repeating textures should use load_texture
+    // Textures that are too big for a page get their own layer like with load_texture
+    // Returns None if a texture with this label is already loaded
+    pub fn load_atlas_texture(&mut self, bytes:&[u8], label: String) -> Result<Option<TextureRegion>, RendererError> {
+        if self.loaded_textures.contains_key(&label) {
+            return Ok(None);
+        }
+        let img = image::load_from_memory(bytes)?;
+
+        use image::GenericImageView;
+        let (region, cmd_buffers) = if self.texture_atlas.fits(&self.texture_array, img.width(), img.height()) {
+            self.texture_atlas.add_image(&self.device, &mut self.texture_array, &img)?
+        } else {
+            let (region, cmd_buffer) = self.texture_array.add_layer(&self.device, &img)?;
+            (region, vec![cmd_buffer])
+        };
+        self.queue.submit(&cmd_buffers);
+        self.loaded_textures.insert(label, region);
+        // The texture array might have been recreated with more layers
+        self.texture_bind_group = create_texture_bind_group(&self.device, &self.texture_bind_group_layout, &self.texture_array);
+        Ok(Some(region))
+    }
+
+    // Every shape drawn with this texture uses the sampler unless set_sampler overrides it
+    // Atlas textures should stay with clamping samplers, repeating would sample the neighbouring images in the page
+    pub fn set_texture_sampler(&mut self, label: &str, sampler: Sampler) {
+        self.texture_samplers.insert(String::from(label), sampler);
+    }
+
+    // Every following draw uses this sampler until it is set to None again
+    // The sampler is stored in the vertices, so changing it doesn't flush the batch
+    pub fn set_sampler(&mut self, sampler: Option<Sampler>) {
+        self.sampler_override = sampler;
+    }
+
+    // Every following draw uses this blend mode
+    // A batch can only have one blend mode, so batches are split where consecutive draws use different modes
+    pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+        self.blend_mode = blend_mode;
+    }
+
+    // Compiles the material's shaders so draws can use it with set_material
+    // Materials without uniform data use the same bind groups as the default material
+    // Returns false if a material with this name is already registered
+    pub fn register_material(&mut self, name: &str, desc: &MaterialDescriptor) -> Result<bool, RendererError> {
+        if self.material_ids.contains_key(name) {
+            return Ok(false);
+        }
+        let vertex_shader = desc.vertex_shader.clone().unwrap_or_else(|| self.materials[DEFAULT_MATERIAL].vertex_shader.clone());
+        let fragment_shader = desc.fragment_shader.clone();
+        let shaders_modified = shader::get_modified_time(&[&vertex_shader, &fragment_shader]);
+        let (vs_module, fs_module) = shader::create_shaders(&vertex_shader, &fragment_shader, &self.device)?;
+
+        let uniforms = desc.uniform_data.map(|data| create_material_uniforms(&self.device, &self.material_bind_group_layout, data));
+
+        let layout = match uniforms {
+            Some(_) => self.device.create_pipeline_layout(
+                &wgpu::PipelineLayoutDescriptor {
+                    bind_group_layouts: &[&self.uniform_bind_group_layout, &self.texture_sampler_bind_group_layout, &self.texture_bind_group_layout, &self.material_bind_group_layout],
+                }
+            ),
+            None => self.device.create_pipeline_layout(
+                &wgpu::PipelineLayoutDescriptor {
+                    bind_group_layouts: &[&self.uniform_bind_group_layout, &self.texture_sampler_bind_group_layout, &self.texture_bind_group_layout],
+                }
+            ),
+        };
+
+        self.materials.push(Material {
+            vertex_shader,
+            fragment_shader,
+            shaders_modified,
+            settings: PipelineSettings {
+                layout,
+                vs_module,
+                fs_module,
+                format: self.format,
+                index_format: self.index_format,
+                sample_count: self.sample_count,
             },
+            pipelines: HashMap::new(),
+            uniforms,
+        });
+        self.material_ids.insert(String::from(name), self.materials.len() - 1);
+        Ok(true)
+    }
+
+    // Replaces the uniform data of the material
+    // Draws are only batched in end_render, so every draw of the frame sees the last data set before it
+    // The data reaches the gpu with the next batch that is rendered
+    // Materials registered without uniform data have no room for any
+    pub fn set_material_uniforms(&mut self, name: &str, data: &[u8]) -> Result<(), RendererError> {
+        let id = match self.material_ids.get(name) {
+            Some(id) => *id,
+            None => return Err(RendererError::UnknownMaterial(String::from(name))),
+        };
+        let size = data.len() as u64;
+        match &self.materials[id].uniforms {
+            Some(uniforms) if size <= uniforms.size => {},
+            Some(uniforms) => return Err(RendererError::UniformDataTooLarge { size, capacity: uniforms.size }),
+            None => return Err(RendererError::UniformDataTooLarge { size, capacity: 0 }),
+        }
+
+        // The data is copied into the material's buffer together with the next batch
+        let start = self.material_staging_used;
+        let end = start + get_aligned_size(size) as usize;
+        if end as u64 > self.material_staging.get_size() {
+            let new_size = (end as u64).max(self.material_staging.get_size().saturating_mul(2));
+            self.material_staging.grow(&self.device, new_size, start)?;
+        }
+        self.material_staging.get_mapping(&self.device)?[start..start + data.len()].copy_from_slice(data);
+        self.material_staging_used = end;
+        self.material_uploads.push((id, start..start + data.len()));
+        Ok(())
+    }
+
+    // Following draws are mapped into the viewport instead of the whole frame, None goes back to the whole frame
+    // Each viewport usually has a camera of its own, set with update after the viewport
+    // Scissor rectangles and clip masks pushed while a viewport is set are placed inside it and keep their place
+    // if the viewport changes before pop_clip; viewports are reset by end_render
+    pub fn set_viewport(&mut self, viewport: Option<Viewport>) {
+        self.viewport = viewport;
+    }
+
+    // Every following draw uses this material, None goes back to the default material
+    // Unknown materials use the default material
+    // A batch can only have one material, so batches are split where consecutive draws use different materials
+    pub fn set_material(&mut self, name: Option<&str>) {
+        self.material = name.and_then(|name| self.material_ids.get(name).copied()).unwrap_or(DEFAULT_MATERIAL);
+    }
+
+    // Every following draw goes onto this layer
+    // Higher layers are drawn on top of lower layers, draws on the same layer are drawn in the order they were made
+    pub fn set_layer(&mut self, layer: i32) {
+        self.layer = layer;
+    }
+
+    // Following draws only change pixels inside the rectangle until pop_clip is called
+    // The rectangle is relative to the current viewport, or to the frame if there is none
+    pub fn push_scissor_rect(&mut self, rect: ScissorRect) {
+        let rect = match self.viewport {
+            Some(viewport) => viewport.to_frame_rect(rect),
+            None => rect,
+        };
+        self.clip_stack.push_scissor(rect);
+    }
+
+    // Following draws only change pixels covered by the shape until pop_clip is called
+    // Only the shape's triangles matter, its colors and textures are ignored
+    pub fn push_clip_mask<'a, T:Drawable<'a>>(&mut self, shape: &'a T, transformation: Option<&UsableTransform>) {
+        let (shape_indices, shape_vertices) = shape.get_vertex_information();
+        let mut vertices: Vec<Vertex> = shape_vertices.to_vec();
+        transform_vertices(&mut vertices, transformation);
+
+        self.clip_masks.push(ClipMask {
+            indices: shape_indices.to_offset_vec(0),
+            vertices,
+            camera: self.cameras.len() - 1,
+            viewport: self.viewport,
+        });
+        self.clip_stack.push_mask(self.clip_masks.len() - 1);
+    }
+
+    // Removes the last pushed scissor rectangle or clip mask
+    // Fails if nothing is left to pop, the following draws stay unclipped then
+    pub fn pop_clip(&mut self) -> Result<(), RendererError> {
+        if self.clip_stack.pop() {
+            Ok(())
+        } else {
+            Err(RendererError::NoClipToPop)
+        }
+    }
+
+    // A batch can only have one blend mode, material, clip state, camera and viewport, the batch is flushed if any of them changes
+    fn set_batch_state(&mut self, blend_mode: BlendMode, material: usize, clip: usize, camera: usize, viewport: Option<Viewport>) -> Result<(), RendererError> {
+        let clip_changed = self.clip_stack.get(clip) != self.clip_stack.get(self.batch_clip);
+        let view_changed = camera != self.batch_camera || viewport != self.batch_viewport;
+        if blend_mode == self.batch_blend_mode && material == self.batch_material && !clip_changed && !view_changed {
+            return Ok(());
+        }
+        // Meshes don't use the batch's buffers
+        if self.num_indices > 0 || !self.batch_items.is_empty() {
+            self.render(FlushReason::StateChange)?;
+        }
+        self.batch_blend_mode = blend_mode;
+        self.batch_material = material;
+        self.batch_clip = clip;
+        self.batch_camera = camera;
+        self.batch_viewport = viewport;
+
+        // Pipelines are created the first time a material is used with a blend mode
+        let material = &mut self.materials[material];
+        if let hash_map::Entry::Vacant(entry) = material.pipelines.entry(blend_mode) {
+            entry.insert(create_render_pipeline(&self.device, &material.settings, blend_mode));
+        }
+
+        let masks = self.clip_stack.get(clip).masks.clone();
+        // Masks keep the camera and viewport they were pushed with, so the stencil buffer stays valid when only those change
+        if masks != self.stencil_masks {
+            // The stencil buffer is cleared and the new masks are drawn at the start of the batch
+            self.clear_stencil = true;
+            let clip_masks = std::mem::take(&mut self.clip_masks);
+            let result = masks.iter().enumerate().try_for_each(|(stencil_value, mask)| {
+                self.add_mask_to_batch(stencil_value as u32, &clip_masks[*mask])
+            });
+            self.clip_masks = clip_masks;
+            self.stencil_masks = masks;
+            result?;
+        }
+        Ok(())
+    }
+
+    // Creates the stencil buffer and the multisampled color texture if there are none with the frame's size
+    // A resized window gives frames with the new size, so the attachments follow it
+    // The least recently used attachments are dropped once there are more than MAX_FRAME_ATTACHMENTS
+    fn prepare_frame_attachments(&mut self) -> Result<&FrameAttachments, RendererError> {
+        let (width, height) = match &self.frame {
+            Some(frame) => (frame.width, frame.height),
+            None => return Err(RendererError::NoRenderInProgress),
+        };
+        let existing = self.frame_attachments.iter().position(|frame_attachments| frame_attachments.width == width && frame_attachments.height == height);
+
+        if let Some(i) = existing {
+            let frame_attachments = self.frame_attachments.remove(i);
+            self.frame_attachments.push(frame_attachments);
+        } else {
+            let sample_count = self.sample_count;
+            let stencil = create_attachment_texture(&self.device, "stencil_attachment", width, height, sample_count, STENCIL_FORMAT, wgpu::TextureUsage::OUTPUT_ATTACHMENT);
+            let multisampled_color = if sample_count > 1 {
+                Some(create_attachment_texture(&self.device, "multisampled_color_attachment", width, height, sample_count, self.format, wgpu::TextureUsage::OUTPUT_ATTACHMENT))
+            } else {
+                None
+            };
+            self.frame_attachments.push(FrameAttachments {
+                stencil,
+                multisampled_color,
+                width,
+                height,
+            });
+            if self.frame_attachments.len() > MAX_FRAME_ATTACHMENTS {
+                self.frame_attachments.remove(0);
+            }
+        }
+        // Never empty at this point
+        Ok(self.frame_attachments.last().unwrap())
+    }
+
+    // The most vertices a single batch can hold
+    fn get_vertex_limit(&self) -> u32 {
+        let limit = self.max_vertices.unwrap_or(u32::MAX);
+        match self.index_format {
+            // Uint16 indices can only address the first 65536 vertices of a batch
+            wgpu::IndexFormat::Uint16 => limit.min(MAX_UINT16_INDEXED_VERTICES),
+            wgpu::IndexFormat::Uint32 => limit,
+        }
+    }
+
+    fn get_index_limit(&self) -> u32 {
+        self.max_indices.unwrap_or(u32::MAX)
+    }
+
+    // Makes sure the current batch has room for the given number of vertices and indices
+    // The batch is flushed if it would go over the limits, otherwise the buffers grow if needed
+    fn reserve(&mut self, vertices: u32, indices: u32) -> Result<(), RendererError> {
+        let vertex_limit = self.get_vertex_limit();
+        let index_limit = self.get_index_limit();
+        if self.num_vertices as u64 + vertices as u64 > vertex_limit as u64 {
+            self.render(FlushReason::VertexCapacity)?;
+        } else if self.num_indices as u64 + indices as u64 > index_limit as u64 {
+            self.render(FlushReason::IndexCapacity)?;
+        }
+
+        let needed_vertices = self.num_vertices + vertices;
+        if needed_vertices > self.vertex_capacity {
+            let new_capacity = needed_vertices.max(self.vertex_capacity.saturating_mul(2)).min(vertex_limit);
+            self.grow_vertex_buffer(new_capacity)?;
         }
+        let needed_indices = self.num_indices + indices;
+        if needed_indices > self.index_capacity {
+            let new_capacity = needed_indices.max(self.index_capacity.saturating_mul(2)).min(index_limit);
+            self.grow_index_buffer(new_capacity)?;
+        }
+        Ok(())
+    }
+
+    // Replaces the vertex buffer with a bigger one, the vertices of the current batch are kept in the staging buffer
+    fn grow_vertex_buffer(&mut self, new_capacity: u32) -> Result<(), RendererError> {
+        let size = (std::mem::size_of::<Vertex>() as u64 * new_capacity as u64) as wgpu::BufferAddress;
+        let used_bytes = self.num_vertices as usize * std::mem::size_of::<Vertex>();
+        self.vertex_staging.grow(&self.device, size, used_bytes)?;
+        self.vertex_buffer = create_target_buffer(&self.device, "Vertex buffer", wgpu::BufferUsage::VERTEX, size);
+        self.vertex_capacity = new_capacity;
+        Ok(())
     }
 
-    fn add_to_index_buffer(&mut self, indices: &[u16]) {
-        let index_buffer_data = self.index_buffer_write_mapping.as_slice();
+    // Replaces the index buffer with a bigger one, the indices of the current batch are kept in the staging buffer
+    fn grow_index_buffer(&mut self, new_capacity: u32) -> Result<(), RendererError> {
+        let size = (index_format_size(self.index_format) * new_capacity as u64) as wgpu::BufferAddress;
+        let used_bytes = self.num_indices as usize * index_format_size(self.index_format) as usize;
+        self.index_staging.grow(&self.device, size, used_bytes)?;
+        self.index_buffer = create_target_buffer(&self.device, "Index buffer", wgpu::BufferUsage::INDEX, size);
+        self.index_capacity = new_capacity;
+        Ok(())
+    }
+
+    fn add_to_index_buffer(&mut self, indices: &[u32]) -> Result<(), RendererError> {
+        let index_size = index_format_size(self.index_format) as usize;
+        let index_buffer_data = self.index_staging.get_mapping(&self.device)?;
         let range = 
-            (self.num_indices as usize * std::mem::size_of::<u16>())..
-            ((self.num_indices as usize + indices.len())*std::mem::size_of::<u16>());
-        index_buffer_data[range].copy_from_slice(bytemuck::cast_slice(indices));
+            (self.num_indices as usize * index_size)..
+            ((self.num_indices as usize + indices.len()) * index_size);
+        match self.index_format {
+            wgpu::IndexFormat::Uint16 => {
+                let indices: Vec<u16> = indices.iter().map(|i| *i as u16).collect();
+                index_buffer_data[range].copy_from_slice(bytemuck::cast_slice(&indices));
+            },
+            wgpu::IndexFormat::Uint32 => {
+                index_buffer_data[range].copy_from_slice(bytemuck::cast_slice(indices));
+            },
+        }
         self.num_indices += indices.len() as u32;
+        self.frame_stats.indices += indices.len() as u64;
+        Ok(())
     }
 
-    fn add_to_vertex_buffer(&mut self, vertices: &[Vertex]) {
-        let vertex_buffer_data = self.vertex_buffer_write_mapping.as_slice();
+    fn add_to_vertex_buffer(&mut self, vertices: &[Vertex]) -> Result<(), RendererError> {
+        let vertex_buffer_data = self.vertex_staging.get_mapping(&self.device)?;
         let range = 
             (self.num_vertices as usize * std::mem::size_of::<Vertex>())..
             ((self.num_vertices as usize + vertices.len()) * std::mem::size_of::<Vertex>());
         vertex_buffer_data[range].copy_from_slice(bytemuck::cast_slice(vertices));
         self.num_vertices += vertices.len() as u32;
+        self.frame_stats.vertices += vertices.len() as u64;
+        Ok(())
     }
 
-    pub fn draw<'a, T:Drawable<'a>>(&mut self, shape: &'a T, transformation: Option<&UsableTransform>) {
-        let (shape_indices, shape_vertices) = shape.get_vertex_information::<>();
+    // Unknown textures use the placeholder
+    fn get_texture_region(&self, name: &str) -> TextureRegion {
+        match self.loaded_textures.get(name) {
+            Some(region) => *region,
+            None => self.loaded_textures[PLACEHOLDER_TEXTURE_NAME],
+        }
+    }
 
-        if shape_indices.len() > (self.max_indices-self.num_indices) as usize {
-            if shape_indices.len() > self.max_indices as usize {
-                panic!("shape has more indices than the renderer's max_indices");
+    // Copies the vertices of the shape, pointing to their texture region and sampler
+    fn prepare_vertices<'a, T:Drawable<'a>>(&self, shape: &'a T) -> Vec<Vertex> {
+        let (_, shape_vertices) = shape.get_vertex_information::<>();
+        let mut vertices: Vec<Vertex> = shape_vertices.to_vec();
+
+        let mut sampler = self.sampler_override;
+        if let Some(name) = shape.get_texture_name() {
+            let region = self.get_texture_region(&name);
+            sampler = sampler.or_else(|| self.texture_samplers.get(&name).copied());
+            for vertex in &mut vertices {
+                vertex.texture_binding = region.layer as i32;
+                region.map_tex_coords(&mut vertex.tex_coords_or_color);
             }
-            self.render();
         }
-        if shape_vertices.len() > (self.max_vertices-self.num_vertices) as usize {
-            if shape_vertices.len() > self.max_vertices as usize {
-                panic!("shape has more vertices than the renderer's max_vertices");
+        if let Some(sampler) = sampler {
+            for vertex in &mut vertices {
+                vertex.sampler = sampler as u32;
             }
-            self.render();
         }
+        vertices
+    }
 
-        let mut indices: Vec<u16> = shape_indices.to_vec();
-        for index in &mut indices {
-            *index += self.num_vertices as u16;
-        }
+    // Like prepare_vertices but the texture region is returned instead of being applied, so the tex coords stay in 0..1
+    // The region is applied per instance, which can replace it with another texture
+    fn prepare_instanced_vertices<'a, T:Drawable<'a>>(&self, shape: &'a T) -> (Vec<Vertex>, Option<TextureRegion>) {
+        let (_, shape_vertices) = shape.get_vertex_information::<>();
+        let mut vertices: Vec<Vertex> = shape_vertices.to_vec();
 
         let texture_name = shape.get_texture_name();
-        
-        match texture_name {
-            Some(_) => {
-                let mut current_texture_binding = 0;
-                for i in 0..self.draw_call_textures.len() {
-                    if self.draw_call_textures[i] == None {
-                        self.draw_call_textures[i] = texture_name;
-                        current_texture_binding = i;
-                        break;
-                    } else if self.draw_call_textures[i] == texture_name {
-                        current_texture_binding = i;
-                        break;
-                    }
-                    // Out of texture binding locations
-                    // Render the current information, then add the texture to the new empty array
-                    if i == self.draw_call_textures.len()-1 {
-                        self.render();
-                        // Put the texture in the first slot int he textures array
-                        // The array should be cleared in render(); if it isn't, something has gone terribly wrong
-                        assert_eq!(self.draw_call_textures[0], None);
-                        self.draw_call_textures[0] = texture_name;
-                        current_texture_binding = 0;
-                        // All the indices in shape_indices were incremented with the old number of vertices so it needs to be remade
-                        // Because the new number of indices should start at 0, no need to add self.num_vertices
-                        indices = shape_indices.to_vec();
-                        break; // Logically unnecessary, but the borrow checker will complain without it
-                    }
+        let region = texture_name.as_ref().map(|name| self.get_texture_region(name));
+        if let Some(region) = region {
+            for vertex in &mut vertices {
+                vertex.texture_binding = region.layer as i32;
+            }
+        }
+        let sampler = match &texture_name {
+            Some(name) => self.sampler_override.or_else(|| self.texture_samplers.get(name).copied()),
+            None => self.sampler_override,
+        };
+        if let Some(sampler) = sampler {
+            for vertex in &mut vertices {
+                vertex.sampler = sampler as u32;
+            }
+        }
+        (vertices, region)
+    }
+
+    // Queues the shape on the current layer, it is put into a batch by end_render
+    pub fn draw<'a, T:Drawable<'a>>(&mut self, shape: &'a T, transformation: Option<&UsableTransform>) {
+        let start = Instant::now();
+        let (shape_indices, _) = shape.get_vertex_information::<>();
+        let vertices = self.prepare_vertices(shape);
+        if let Some(name) = shape.get_texture_name() {
+            self.record_sampled_target(&name);
+        }
+
+        // Only the matrix is computed here, the vertices are transformed by shader.vs
+        self.queue_draw(QueuedGeometry::Shape {
+            indices: shape_indices.to_offset_vec(0),
+            vertices,
+            transform: transformation.map(|transf| padded_columns(&transf.get_transformation_matrix())),
+        });
+        self.frame_stats.draw_time += start.elapsed();
+    }
+
+    // A target drawn into itself shows its previous contents, which is allowed
+    fn record_sampled_target(&mut self, name: &str) {
+        if self.render_targets.contains_key(name) && self.current_target.as_deref() != Some(name) {
+            self.sampled_targets.insert(String::from(name));
+        }
+    }
+
+    fn queue_draw(&mut self, geometry: QueuedGeometry) {
+        self.draw_queue.push(QueuedDraw {
+            layer: self.layer,
+            blend_mode: self.blend_mode,
+            material: self.material,
+            clip: self.clip_stack.current(),
+            camera: self.cameras.len() - 1,
+            viewport: self.viewport,
+            geometry,
+        });
+        self.last_camera_used = true;
+    }
+
+    // Uploads the shape into buffers of its own, so drawing it with draw_mesh doesn't copy any vertices
+    // The texture region and sampler are looked up now, so the shape's texture has to be loaded first
+    pub fn create_mesh<'a, T:Drawable<'a>>(&mut self, shape: &'a T) -> MeshHandle {
+        let (shape_indices, _) = shape.get_vertex_information::<>();
+        let indices = shape_indices.to_offset_vec(0);
+        let (vertices, texture_region) = self.prepare_instanced_vertices(shape);
+        let texture_layers = match texture_region {
+            Some(_) => vec![],
+            None => get_texture_layers(&vertices).into_iter().collect(),
+        };
+
+        // Meshes are drawn with the same pipelines as batches, so Uint16 indices limit the vertices of a draw call
+        let vertex_limit = match self.index_format {
+            wgpu::IndexFormat::Uint16 => MAX_UINT16_INDEXED_VERTICES,
+            wgpu::IndexFormat::Uint32 => u32::MAX,
+        };
+        let parts = if vertices.len() > vertex_limit as usize {
+            split_shape(IndexSlice::U32(&indices), &vertices, vertex_limit, u32::MAX)
+        } else {
+            vec![ShapePart { indices, vertices }]
+        };
+
+        let mesh = Mesh {
+            parts: parts.iter().map(|part| {
+                let index_buffer = match self.index_format {
+                    wgpu::IndexFormat::Uint16 => {
+                        let indices: Vec<u16> = part.indices.iter().map(|i| *i as u16).collect();
+                        self.device.create_buffer_with_data(bytemuck::cast_slice(&indices), wgpu::BufferUsage::INDEX)
+                    },
+                    wgpu::IndexFormat::Uint32 => {
+                        self.device.create_buffer_with_data(bytemuck::cast_slice(&part.indices), wgpu::BufferUsage::INDEX)
+                    },
+                };
+                MeshPart {
+                    vertex_buffer: self.device.create_buffer_with_data(bytemuck::cast_slice(&part.vertices), wgpu::BufferUsage::VERTEX),
+                    index_buffer,
+                    num_vertices: part.vertices.len() as u32,
+                    num_indices: part.indices.len() as u32,
                 }
-                let mut vertices: Vec<Vertex> = shape_vertices.to_vec();
-                match transformation {
-                    Some(transf) => {
-                        let transformation_matrix = transf.get_transformation_matrix();
-                        for vertex in &mut vertices {
-                            vertex.texture_binding = current_texture_binding as i32;
-                            UsableTransform::transform_point_with_matrix(&mut vertex.position, &transformation_matrix)
-                        }
-                    }
-                    None => {
-                        for vertex in &mut vertices {
-                            vertex.texture_binding = current_texture_binding as i32;
+            }).collect(),
+            texture_name: shape.get_texture_name(),
+            texture_region,
+            texture_layers,
+        };
+        self.meshes.insert(mesh)
+    }
+
+    // Frees the mesh's buffers, draws of the handle that are still queued draw nothing
+    #[allow(dead_code)]
+    pub fn remove_mesh(&mut self, mesh: MeshHandle) -> Result<(), RendererError> {
+        match self.meshes.remove(mesh) {
+            Some(_) => Ok(()),
+            None => Err(RendererError::UnknownMesh),
+        }
+    }
+
+    // Queues the mesh like draw queues a shape, only the transformation and texture region are sent to the gpu
+    // A texture name replaces the texture of a textured mesh like it does for instances, colored meshes ignore it
+    // Handles of removed meshes draw nothing
+    // Custom vertex shaders of materials have to apply the instance transform for meshes to end up in the right place
+    pub fn draw_mesh(&mut self, mesh: MeshHandle, transformation: Option<&UsableTransform>, texture_name: Option<&str>) {
+        let start = Instant::now();
+        let (texture_region, mesh_texture_name) = match self.meshes.get(mesh) {
+            Some(mesh) => (mesh.texture_region, mesh.texture_name.clone()),
+            None => return,
+        };
+        let mut instance = Instance::new(transformation);
+        if let (Some(region), Some(mesh_texture_name)) = (texture_region, mesh_texture_name) {
+            let name = texture_name.unwrap_or(&mesh_texture_name);
+            instance.set_texture_region(match texture_name {
+                Some(name) => self.get_texture_region(name),
+                None => region,
+            });
+            self.record_sampled_target(name);
+        }
+        self.queue_draw(QueuedGeometry::Mesh {
+            mesh,
+            instance,
+        });
+        self.frame_stats.draw_time += start.elapsed();
+    }
+
+    // Queues the shape once, it is drawn for every instance with a single draw call
+    // Instances of a textured shape can replace its texture, they all use the sampler of the shape's texture
+    pub fn draw_instanced<'a, T:Drawable<'a>>(&mut self, shape: &'a T, instances: &[DrawInstance]) {
+        if instances.is_empty() {
+            return;
+        }
+        let start = Instant::now();
+        let (shape_indices, _) = shape.get_vertex_information::<>();
+        let (vertices, shape_region) = self.prepare_instanced_vertices(shape);
+        if let Some(shape_texture_name) = shape.get_texture_name() {
+            for instance in instances {
+                self.record_sampled_target(instance.texture_name.unwrap_or(&shape_texture_name));
+            }
+        }
+
+        let instances = instances.iter().map(|instance| {
+            let region = shape_region.map(|region| match instance.texture_name {
+                Some(name) => self.get_texture_region(name),
+                None => region,
+            });
+            Instance::with_texture(&instance.transform, instance.tint, region)
+        }).collect();
+
+        self.queue_draw(QueuedGeometry::Instanced {
+            indices: shape_indices.to_offset_vec(0),
+            vertices,
+            instances,
+        });
+        self.frame_stats.draw_time += start.elapsed();
+    }
+
+    // Puts the queued draws into batches from the lowest to the highest layer
+    fn flush_draw_queue(&mut self) -> Result<(), RendererError> {
+        let mut draw_queue = std::mem::take(&mut self.draw_queue);
+        // The sort is stable, so draws on the same layer keep their order and transparent shapes still blend back to front
+        draw_queue.sort_by_key(|queued_draw| queued_draw.layer);
+        for queued_draw in &mut draw_queue {
+            self.set_batch_state(queued_draw.blend_mode, queued_draw.material, queued_draw.clip, queued_draw.camera, queued_draw.viewport)?;
+            match &mut queued_draw.geometry {
+                QueuedGeometry::Shape { indices, vertices, transform } => {
+                    self.frame_texture_layers.extend(get_texture_layers(vertices));
+                    self.add_to_batch(indices, vertices, *transform, None)?;
+                },
+                QueuedGeometry::Mesh { mesh, instance } => {
+                    if let Some(mesh) = self.meshes.get(*mesh) {
+                        self.frame_texture_layers.extend(&mesh.texture_layers);
+                        if instance.texture_layer >= 0 {
+                            self.frame_texture_layers.insert(instance.texture_layer as u32);
                         }
-                    }
-                }
-                self.add_to_vertex_buffer(&vertices);
-                self.add_to_index_buffer(&indices);
-            },
-            None => {
-                match transformation {
-                    Some(transf) => {
-                        let mut vertices: Vec<Vertex> = shape_vertices.to_vec();
-                        let transformation_matrix = transf.get_transformation_matrix();
-                        for vertex in &mut vertices {
-                            UsableTransform::transform_point_with_matrix(&mut vertex.position, &transformation_matrix)
+                        for part in &mesh.parts {
+                            self.frame_stats.vertices += part.num_vertices as u64;
+                            self.frame_stats.indices += part.num_indices as u64;
                         }
-                        self.add_to_vertex_buffer(&vertices);
-                        self.add_to_index_buffer(&indices);
                     }
-                    None => {
-                        self.add_to_vertex_buffer(shape_vertices);
-                        self.add_to_index_buffer(&indices);
+                    self.batch_instances.push(*instance);
+                    self.batch_items.push(BatchItem::Mesh {
+                        mesh: *mesh,
+                        instance: self.batch_instances.len() as u32 - 1,
+                    });
+                },
+                QueuedGeometry::Instanced { indices, vertices, instances } => {
+                    // Instances of textured shapes pick the layer, colored shapes ignore it
+                    if !get_texture_layers(vertices).is_empty() {
+                        self.frame_texture_layers.extend(instances.iter().map(|instance| instance.texture_layer as u32));
                     }
-                }
-                
+                    // The vertices are drawn once per instance
+                    self.frame_stats.vertices += (vertices.len() * (instances.len() - 1)) as u64;
+                    self.frame_stats.indices += (indices.len() * (instances.len() - 1)) as u64;
+                    self.add_to_batch(indices, vertices, None, Some(instances))?;
+                },
+            }
+        }
+        Ok(())
+    }
+
+    // Without a transform the vertices are copied as they are, so their transform_id has to be the identity
+    // Without instances the vertices are drawn once with the identity instance
+    fn add_to_batch(&mut self, indices: &[u32], vertices: &mut [Vertex], transform: Option<[[f32; 4]; 3]>, instances: Option<&[Instance]>) -> Result<(), RendererError> {
+        let vertex_limit = self.get_vertex_limit();
+        let index_limit = self.get_index_limit();
+        if vertices.len() > vertex_limit as usize || indices.len() > index_limit as usize {
+            // The shape can never fit into a single batch so it gets drawn in several parts
+            let mut parts = split_shape(IndexSlice::U32(indices), vertices, vertex_limit, index_limit);
+            for part in &mut parts {
+                self.add_to_batch(&part.indices, &mut part.vertices, transform, instances)?;
+            }
+            return Ok(());
+        }
+        // Reserving can render the batch, which drops its transforms and instances, so they are added afterwards
+        self.reserve(vertices.len() as u32, indices.len() as u32)?;
+        if let Some(transform) = transform {
+            self.batch_transforms.push(transform);
+            let transform_id = self.batch_transforms.len() as u32 - 1;
+            for vertex in vertices.iter_mut() {
+                vertex.transform_id = transform_id;
+            }
+        }
+        let instances = match instances {
+            Some(instances) => {
+                let first = self.batch_instances.len() as u32;
+                self.batch_instances.extend_from_slice(instances);
+                first..self.batch_instances.len() as u32
+            },
+            None => 0..1,
+        };
+
+        let start = self.num_indices;
+        let indices: Vec<u32> = IndexSlice::U32(indices).to_offset_vec(self.num_vertices);
+        self.add_to_vertex_buffer(vertices)?;
+        self.add_to_index_buffer(&indices)?;
+
+        // Consecutive shapes with the same instances are drawn with a single draw call
+        match self.batch_items.last_mut() {
+            Some(BatchItem::Shapes { indices: range, instances: range_instances }) if range.end == start && *range_instances == instances => {
+                range.end = self.num_indices;
+            },
+            _ => self.batch_items.push(BatchItem::Shapes { indices: start..self.num_indices, instances }),
+        }
+        Ok(())
+    }
+
+    // Masks are always added at the start of a batch, before any of the batch's shapes
+    fn add_mask_to_batch(&mut self, stencil_value: u32, mask: &ClipMask) -> Result<(), RendererError> {
+        let vertex_limit = self.get_vertex_limit();
+        let index_limit = self.get_index_limit();
+        if mask.vertices.len() > vertex_limit as usize || mask.indices.len() > index_limit as usize {
+            let parts = split_shape(IndexSlice::U32(&mask.indices), &mask.vertices, vertex_limit, index_limit);
+            for part in parts {
+                self.add_mask_to_batch(stencil_value, &ClipMask {
+                    indices: part.indices,
+                    vertices: part.vertices,
+                    camera: mask.camera,
+                    viewport: mask.viewport,
+                })?;
+            }
+            return Ok(());
+        }
+        let (indices, vertices) = (&mask.indices, &mask.vertices);
+        self.reserve(vertices.len() as u32, indices.len() as u32)?;
+
+        let start = self.num_indices;
+        let indices: Vec<u32> = IndexSlice::U32(indices).to_offset_vec(self.num_vertices);
+        self.add_to_vertex_buffer(vertices)?;
+        self.add_to_index_buffer(&indices)?;
+        self.mask_draws.push(MaskDraw {
+            stencil_value,
+            indices: start..self.num_indices,
+            camera: mask.camera,
+            viewport: mask.viewport,
+        });
+        Ok(())
+    }
+}
+
+impl ShapeRenderer for Renderer {
+    fn draw<'a, T:Drawable<'a>>(&mut self, shape: &'a T, transformation: Option<&UsableTransform>) {
+        Renderer::draw(self, shape, transformation);
+    }
+
+    fn set_sampler(&mut self, sampler: Option<Sampler>) {
+        Renderer::set_sampler(self, sampler);
+    }
+
+    fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+        Renderer::set_blend_mode(self, blend_mode);
+    }
+
+    fn set_layer(&mut self, layer: i32) {
+        Renderer::set_layer(self, layer);
+    }
+
+    fn create_mesh<'a, T:Drawable<'a>>(&mut self, shape: &'a T) -> MeshHandle {
+        Renderer::create_mesh(self, shape)
+    }
+
+    fn draw_mesh(&mut self, mesh: MeshHandle, transformation: Option<&UsableTransform>, texture_name: Option<&str>) {
+        Renderer::draw_mesh(self, mesh, transformation, texture_name);
+    }
+
+    fn draw_instanced<'a, T:Drawable<'a>>(&mut self, shape: &'a T, instances: &[DrawInstance]) {
+        Renderer::draw_instanced(self, shape, instances);
+    }
+
+    fn set_material(&mut self, name: Option<&str>) {
+        Renderer::set_material(self, name);
+    }
+
+    fn update(&mut self, camera: &Camera) {
+        Renderer::update(self, camera);
+    }
+
+    fn set_viewport(&mut self, viewport: Option<Viewport>) {
+        Renderer::set_viewport(self, viewport);
+    }
+
+    fn push_scissor_rect(&mut self, rect: ScissorRect) {
+        Renderer::push_scissor_rect(self, rect);
+    }
+
+    fn push_clip_mask<'a, T:Drawable<'a>>(&mut self, shape: &'a T, transformation: Option<&UsableTransform>) {
+        Renderer::push_clip_mask(self, shape, transformation);
+    }
+
+    fn pop_clip(&mut self) -> Result<(), RendererError> {
+        Renderer::pop_clip(self)
+    }
+}
+
+// The bind group has to be recreated whenever the uniform or transform buffer grows
+fn create_uniform_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, uniform_buffer: &StreamedBuffer, transform_buffer: &StreamedBuffer) -> wgpu::BindGroup {
+    device.create_bind_group(
+        &wgpu::BindGroupDescriptor {
+            layout,
+            bindings: &[
+                wgpu::Binding {
+                    binding: 0,
+                    // One camera, the dynamic offset picks which one
+                    resource: wgpu::BindingResource::Buffer {
+                        buffer: uniform_buffer.get_buffer(),
+                        range: 0..std::mem::size_of::<Uniforms>() as wgpu::BufferAddress,
+                    },
+                },
+                wgpu::Binding {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Buffer {
+                        buffer: transform_buffer.get_buffer(),
+                        range: 0..transform_buffer.get_size(),
+                    },
+                },
+            ],
+            label: Some("uniform_bind_group"),
+        },
+    )
+}
+
+fn create_texture_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, texture_array: &TextureArray) -> wgpu::BindGroup {
+    device.create_bind_group(
+        &wgpu::BindGroupDescriptor {
+            layout,
+            bindings: &[
+                wgpu::Binding {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture_array.view),
+                },
+            ],
+            label: Some("texture_bind_group"),
+        },
+    )
+}
+
+// Everything the render pipelines have in common
+struct PipelineSettings {
+    layout: wgpu::PipelineLayout,
+    vs_module: wgpu::ShaderModule,
+    fs_module: wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+    index_format: wgpu::IndexFormat,
+    sample_count: u32,
+}
+
+fn create_material_uniforms(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, data: &[u8]) -> MaterialUniforms {
+    // Updates are copied in aligned sizes
+    let mapped_buffer = device.create_buffer_mapped(
+        &wgpu::BufferDescriptor {
+            label: Some("Material uniform buffer"),
+            size: get_aligned_size(data.len() as wgpu::BufferAddress),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        }
+    );
+    mapped_buffer.data[..data.len()].copy_from_slice(data);
+    let buffer = mapped_buffer.finish();
+    let bind_group = device.create_bind_group(
+        &wgpu::BindGroupDescriptor {
+            layout,
+            bindings: &[
+                wgpu::Binding {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer {
+                        buffer: &buffer,
+                        range: 0..data.len() as wgpu::BufferAddress,
+                    },
+                },
+            ],
+            label: Some("material_bind_group"),
+        },
+    );
+    MaterialUniforms {
+        buffer,
+        bind_group,
+        size: data.len() as u64,
+    }
+}
+
+fn create_attachment_texture(device: &wgpu::Device, label: &str, width: u32, height: u32, sample_count: u32, format: wgpu::TextureFormat, usage: wgpu::TextureUsage) -> wgpu::Texture {
+    device.create_texture(
+        &wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth: 1,
+            },
+            array_layer_count: 1,
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage,
+        },
+    )
+}
+
+// Layers of the textured vertices, colored vertices have a texture_binding of -1
+fn get_texture_layers(vertices: &[Vertex]) -> HashSet<u32> {
+    vertices.iter()
+        .filter(|vertex| vertex.texture_binding >= 0)
+        .map(|vertex| vertex.texture_binding as u32)
+        .collect()
+}
+
+// Draws a full screen triangle without vertex buffers, blending or a stencil buffer
+fn create_post_pipeline(device: &wgpu::Device, layout: &wgpu::PipelineLayout, vs_module: &wgpu::ShaderModule, fs_module: &wgpu::ShaderModule, format: wgpu::TextureFormat) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(
+        &wgpu::RenderPipelineDescriptor {
+            layout,
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: fs_module,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::None,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            color_states: &[
+                wgpu::ColorStateDescriptor {
+                    format,
+                    color_blend: wgpu::BlendDescriptor::REPLACE,
+                    alpha_blend: wgpu::BlendDescriptor::REPLACE,
+                    write_mask: wgpu::ColorWrite::ALL,
+                },
+            ],
+            depth_stencil_state: None,
+            vertex_state: wgpu::VertexStateDescriptor {
+                index_format: wgpu::IndexFormat::Uint16,
+                vertex_buffers: &[],
+            },
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+    )
+}
+
+// Draws where the stencil value is equal to the reference, which is the number of masks the batch is clipped by
+fn create_render_pipeline(device: &wgpu::Device, settings: &PipelineSettings, blend_mode: BlendMode) -> wgpu::RenderPipeline {
+    create_pipeline(
+        device,
+        settings,
+        wgpu::ColorStateDescriptor {
+            format: settings.format,
+            color_blend: blend_mode.get_color_blend(),
+            alpha_blend: blend_mode.get_alpha_blend(),
+            write_mask: wgpu::ColorWrite::ALL,
+        },
+        wgpu::StencilStateFaceDescriptor {
+            compare: wgpu::CompareFunction::Equal,
+            fail_op: wgpu::StencilOperation::Keep,
+            depth_fail_op: wgpu::StencilOperation::Keep,
+            pass_op: wgpu::StencilOperation::Keep,
+        },
+    )
+}
+
+// Only writes to the stencil buffer: pixels where the stencil value is equal to the reference are incremented
+fn create_mask_pipeline(device: &wgpu::Device, settings: &PipelineSettings) -> wgpu::RenderPipeline {
+    create_pipeline(
+        device,
+        settings,
+        wgpu::ColorStateDescriptor {
+            format: settings.format,
+            color_blend: wgpu::BlendDescriptor::REPLACE,
+            alpha_blend: wgpu::BlendDescriptor::REPLACE,
+            write_mask: wgpu::ColorWrite::empty(),
+        },
+        wgpu::StencilStateFaceDescriptor {
+            compare: wgpu::CompareFunction::Equal,
+            fail_op: wgpu::StencilOperation::Keep,
+            depth_fail_op: wgpu::StencilOperation::Keep,
+            pass_op: wgpu::StencilOperation::IncrementClamp,
+        },
+    )
+}
+
+fn create_pipeline(device: &wgpu::Device, settings: &PipelineSettings, color_state: wgpu::ColorStateDescriptor, stencil_state: wgpu::StencilStateFaceDescriptor) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(
+        &wgpu::RenderPipelineDescriptor {
+            layout: &settings.layout,
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &settings.vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &settings.fs_module,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::None,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            color_states: &[
+                color_state,
+            ],
+            depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+                format: STENCIL_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil_front: stencil_state.clone(),
+                stencil_back: stencil_state,
+                stencil_read_mask: !0,
+                stencil_write_mask: !0,
+            }),
+            vertex_state: wgpu::VertexStateDescriptor {
+                index_format: settings.index_format,
+                vertex_buffers: &[
+                    Vertex::desc(),
+                    Instance::desc(),
+                ],
+            },
+            sample_count: settings.sample_count,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::point::Point;
+    use crate::vertex::{DEFAULT_SAMPLER, IDENTITY_TRANSFORM_ID};
+    use crate::objects::polygons::Rectangle;
+    use crate::software_renderer::SoftwareRenderer;
+    use futures::executor::block_on;
+
+    // The tests using it need a gpu, so they are ignored unless they are run with cargo test -- --ignored
+    fn create_test_renderer() -> Renderer {
+        let renderer = block_on(Renderer::new(
+            &RendererDescriptor {
+                initial_vertices: 100,
+                initial_indices: 150,
+                max_vertices: None,
+                max_indices: None,
+                index_format: wgpu::IndexFormat::Uint16,
+                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                texture_layer_size: 64,
+                sample_count: 1,
+                shader_directory: None,
+                watch_shaders: false,
+            },
+            None,
+        ));
+        match renderer {
+            Ok(renderer) => renderer,
+            Err(error) => panic!("{}", error),
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a gpu"]
+    fn failed_frame_is_cleared_and_post_processed() {
+        let mut renderer = create_test_renderer();
+        renderer.set_post_effects(&[PostEffect::Vignette { strength: 0.5 }]).unwrap();
+        let empty_frame = renderer.render_offscreen(16, 16, |_| {}).unwrap();
+
+        // The post target of this size still holds the square afterwards
+        let square = Rectangle::new(Point { x: -1.0, y: 1.0 }, Point { x: 1.0, y: -1.0 }, None, Some(&[1.0, 0.0, 0.0, 1.0]));
+        let target = renderer.create_offscreen_target(16, 16).unwrap();
+        renderer.begin_render(target.create_frame()).unwrap();
+        renderer.draw(&square, None);
+        renderer.end_render().unwrap();
+
+        // Like end_render when rendering the queued square fails, which can't be caused on purpose
+        renderer.begin_render(target.create_frame()).unwrap();
+        renderer.draw(&square, None);
+        assert!(renderer.finish_frame(Err(RendererError::BufferMapping)).is_err());
+        let failed_frame = renderer.read_offscreen_target(&target).unwrap();
+        assert!(*failed_frame == *empty_frame);
+    }
+
+    // The mask and scissor rectangle are pushed in the left viewport and clip a draw in an overlapping viewport with another camera
+    fn draw_clips_pushed_in_another_viewport<R: ShapeRenderer>(renderer: &mut R) {
+        let mask = Rectangle::new(Point { x: -0.9, y: 0.3 }, Point { x: 0.4, y: -0.3 }, None, Some(&[1.0, 1.0, 1.0, 1.0]));
+        let square = Rectangle::new(Point { x: -1.0, y: 1.0 }, Point { x: 1.0, y: -1.0 }, None, Some(&[1.0, 0.0, 0.0, 1.0]));
+        renderer.set_viewport(Some(Viewport { x: 0, y: 0, width: 8, height: 8 }));
+        renderer.update(&Camera::new());
+        renderer.push_scissor_rect(ScissorRect { x: 2, y: 1, width: 6, height: 6 });
+        renderer.push_clip_mask(&mask, None);
+
+        let mut camera = Camera::new();
+        camera.aspect_ratio = 2.0;
+        renderer.set_viewport(Some(Viewport { x: 4, y: 0, width: 8, height: 8 }));
+        renderer.update(&camera);
+        renderer.draw(&square, None);
+        renderer.pop_clip().unwrap();
+        renderer.pop_clip().unwrap();
+    }
+
+    #[test]
+    #[ignore = "needs a gpu"]
+    fn clips_pushed_in_another_viewport_match_software_renderer() {
+        let mut renderer = create_test_renderer();
+        let image = renderer.render_offscreen(16, 8, draw_clips_pushed_in_another_viewport).unwrap();
+        let expected = SoftwareRenderer::new(16, 8, 64).render(draw_clips_pushed_in_another_viewport);
+        assert!(*image == *expected);
+    }
+
+    // A non-square png with a different color in every pixel
+    fn create_test_png(width: u32, height: u32) -> Vec<u8> {
+        let image = image::RgbaImage::from_fn(width, height, |x, y| image::Rgba([(x*8) as u8, (y*8) as u8, 128, 255]));
+        let mut bytes = vec![];
+        image::DynamicImage::ImageRgba8(image).write_to(&mut bytes, image::ImageOutputFormat::Png).unwrap();
+        bytes
+    }
+
+    #[test]
+    #[ignore = "needs a gpu"]
+    fn textures_keep_their_size() {
+        let mut renderer = create_test_renderer();
+        let png = create_test_png(32, 16);
+        assert!(renderer.load_texture(&png, String::from("small")).unwrap());
+        let region = renderer.get_texture_region("small");
+        assert_eq!(region.uv_scale, [0.5, 0.25]);
+        match renderer.load_texture(&create_test_png(65, 8), String::from("too wide")) {
+            Err(RendererError::InvalidTextureSize { width: 65, height: 8, layer_size: 64 }) => {},
+            result => panic!("{:?}", result),
+        }
+
+        // Every pixel of the frame shows one texel, like in the software renderer that samples the image itself
+        let square = Rectangle::new(Point { x: -1.0, y: 1.0 }, Point { x: 1.0, y: -1.0 }, Some(String::from("small")), None);
+        renderer.set_texture_sampler("small", Sampler::ClampNearest);
+        let image = renderer.render_offscreen(32, 16, |renderer| renderer.draw(&square, None)).unwrap();
+        let mut software_renderer = SoftwareRenderer::new(32, 16, 64);
+        software_renderer.load_texture(&png, String::from("small")).unwrap();
+        software_renderer.set_texture_sampler("small", Sampler::ClampNearest);
+        let expected = software_renderer.render(|renderer| renderer.draw(&square, None));
+        assert!(*image == *expected);
+    }
+
+    #[test]
+    #[ignore = "needs a gpu"]
+    fn draw_before_target_pass_waits_for_frame() {
+        let mut renderer = create_test_renderer();
+        let square = Rectangle::new(Point { x: -0.5, y: 0.5 }, Point { x: 0.5, y: -0.5 }, None, Some(&[1.0, 0.0, 0.0, 1.0]));
+        // The target is shown in the top right quarter, so the square would show up a second time if it ended up in the target
+        let target_rectangle = Rectangle::new(Point { x: 0.0, y: 1.0 }, Point { x: 1.0, y: 0.0 }, Some(String::from("a")), None);
+
+        renderer.create_render_target("a", 8, 8).unwrap();
+        renderer.set_texture_sampler("a", Sampler::ClampNearest);
+        renderer.draw(&square, None);
+        renderer.render_to_target("a", |_| {}).unwrap();
+        let image = renderer.render_offscreen(8, 8, |renderer| renderer.draw(&target_rectangle, None)).unwrap();
+
+        let mut software_renderer = SoftwareRenderer::new(8, 8, 64);
+        software_renderer.create_render_target("a", 8, 8).unwrap();
+        software_renderer.set_texture_sampler("a", Sampler::ClampNearest);
+        software_renderer.draw(&square, None);
+        software_renderer.render_to_target("a", |_| {}).unwrap();
+        let expected = software_renderer.render(|renderer| renderer.draw(&target_rectangle, None));
+        assert!(*image == *expected);
+    }
+
+    // Vertex i is at x = i, so vertices can be told apart after they were remapped
+    fn create_vertices(count: usize) -> Vec<Vertex> {
+        (0..count).map(|i| Vertex {
+            position: Point { x: i as f32, y: 0.0 },
+            texture_binding: -1,
+            tex_coords_or_color: [1.0, 1.0, 1.0, 1.0],
+            sampler: DEFAULT_SAMPLER,
+            transform_id: IDENTITY_TRANSFORM_ID,
+        }).collect()
+    }
+
+    // A strip of triangles that share their edges
+    fn create_strip_indices(triangles: u32) -> Vec<u16> {
+        (0..triangles).flat_map(|i| vec![i as u16, i as u16 + 1, i as u16 + 2]).collect()
+    }
+
+    // The original vertex of every index, in order
+    fn get_triangle_vertices(parts: &[ShapePart]) -> Vec<f32> {
+        parts.iter()
+            .flat_map(|part| part.indices.iter().map(move |i| part.vertices[*i as usize].position.x))
+            .collect()
+    }
+
+    #[test]
+    fn split_shape_that_fits() {
+        let indices = create_strip_indices(4);
+        let vertices = create_vertices(6);
+        let parts = split_shape(IndexSlice::U16(&indices), &vertices, 6, 12);
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].indices, IndexSlice::U16(&indices).to_offset_vec(0));
+        assert_eq!(parts[0].vertices.len(), 6);
+    }
+
+    #[test]
+    fn split_shape_at_limits() {
+        let indices = create_strip_indices(10);
+        let vertices = create_vertices(12);
+        for &(max_vertices, max_indices) in &[(4, 100), (100, 6), (5, 9), (3, 3)] {
+            let parts = split_shape(IndexSlice::U16(&indices), &vertices, max_vertices, max_indices);
+            for part in &parts {
+                assert!(part.vertices.len() <= max_vertices as usize);
+                assert!(part.indices.len() <= max_indices as usize);
+                assert_eq!(part.indices.len() % 3, 0);
             }
+            // Every triangle ends up in a part, in the original order
+            let expected: Vec<f32> = indices.iter().map(|i| *i as f32).collect();
+            assert_eq!(get_triangle_vertices(&parts), expected);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn split_shape_with_u32_indices() {
+        let indices = [0, 1, 2, 2, 1, 3];
+        let vertices = create_vertices(4);
+        let parts = split_shape(IndexSlice::U32(&indices), &vertices, 3, 6);
+        assert_eq!(parts.len(), 2);
+        assert_eq!(get_triangle_vertices(&parts), [0.0, 1.0, 2.0, 2.0, 1.0, 3.0]);
+    }
+}
@@ -0,0 +1,40 @@
+#version 450
+
+// Material that dissolves shapes into noise, see Renderer::register_material
+
+layout (location=0) out vec4 frag_color;
+
+layout (location=0) in VS_OUT {
+    flat int texture_binding;
+    vec4 tex_coords_or_color;
+    flat uint sampler_index;
+    flat vec4 tint;
+} fs_in;
+
+layout(set=1, binding=3) uniform sampler s3;
+
+layout (set=2, binding=0) uniform texture2DArray textures;
+
+// Material uniforms are always set 3, binding 0
+layout (set=3, binding=0) uniform Dissolve {
+    // 0 shows the whole shape, 1 hides it
+    float threshold;
+};
+
+float noise(vec2 p) {
+    return fract(sin(dot(p, vec2(12.9898, 78.233))) * 43758.5453);
+}
+
+void main() {
+    // Blocks of 4x4 pixels disappear together
+    if (noise(floor(gl_FragCoord.xy / 4.0)) < threshold) {
+        discard;
+    }
+    vec4 color;
+    if (fs_in.texture_binding < 0) {
+        color = fs_in.tex_coords_or_color;
+    } else {
+        color = texture(sampler2DArray(textures, s3), vec3(fs_in.tex_coords_or_color.xy, fs_in.texture_binding));
+    }
+    frag_color = color*fs_in.tint;
+}
@@ -0,0 +1,13 @@
+#version 450
+
+// A triangle that covers the whole frame, drawn by every post effect pass without any vertex buffers
+// See post_vignette.fs for the inputs of a post effect's fragment shader
+
+layout (location=0) out vec2 uv;
+
+void main() {
+    vec2 position = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    // Texture coordinates go down while clip space goes up
+    uv = vec2(position.x, 1.0f - position.y);
+    gl_Position = vec4(position*2.0f - 1.0f, 0.0f, 1.0f);
+}
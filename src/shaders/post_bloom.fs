@@ -0,0 +1,21 @@
+#version 450
+
+// Last pass of PostEffect::Bloom, adds the blurred bright parts to the frame the effect started with
+
+layout (location=0) in vec2 uv;
+
+layout (location=0) out vec4 frag_color;
+
+layout (set=0, binding=0) uniform sampler post_sampler;
+layout (set=0, binding=1) uniform texture2D previous;
+layout (set=0, binding=2) uniform texture2D effect_input;
+
+layout (set=2, binding=0) uniform Bloom {
+    float intensity;
+};
+
+void main() {
+    vec4 color = texture(sampler2D(effect_input, post_sampler), uv);
+    vec4 glow = texture(sampler2D(previous, post_sampler), uv);
+    frag_color = vec4(color.rgb + intensity*glow.rgb, color.a);
+}
@@ -0,0 +1,33 @@
+#version 450
+
+// Post effect pass that blurs along one direction, PostEffect::Blur uses a horizontal and a vertical pass
+
+layout (location=0) in vec2 uv;
+
+layout (location=0) out vec4 frag_color;
+
+layout (set=0, binding=0) uniform sampler post_sampler;
+layout (set=0, binding=1) uniform texture2D previous;
+layout (set=0, binding=3) uniform PostUniforms {
+    vec2 texel_size;
+};
+
+layout (set=2, binding=0) uniform Blur {
+    // (1, 0) blurs horizontally and (0, 1) vertically
+    vec2 direction;
+    // In pixels, the gaussian's standard deviation is a third of it
+    float radius;
+};
+
+void main() {
+    int samples = int(ceil(radius));
+    float sigma = max(radius/3.0f, 0.001f);
+    vec4 sum = vec4(0.0f);
+    float weight_sum = 0.0f;
+    for (int i = -samples; i <= samples; i++) {
+        float weight = exp(-float(i*i)/(2.0f*sigma*sigma));
+        sum += weight*texture(sampler2D(previous, post_sampler), uv + float(i)*direction*texel_size);
+        weight_sum += weight;
+    }
+    frag_color = sum/weight_sum;
+}
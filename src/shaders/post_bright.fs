@@ -0,0 +1,21 @@
+#version 450
+
+// First pass of PostEffect::Bloom, keeps only the parts of the frame that are brighter than the threshold
+
+layout (location=0) in vec2 uv;
+
+layout (location=0) out vec4 frag_color;
+
+layout (set=0, binding=0) uniform sampler post_sampler;
+layout (set=0, binding=1) uniform texture2D previous;
+
+layout (set=2, binding=0) uniform Bright {
+    float threshold;
+};
+
+void main() {
+    vec4 color = texture(sampler2D(previous, post_sampler), uv);
+    float luminance = dot(color.rgb, vec3(0.2126f, 0.7152f, 0.0722f));
+    // A soft edge keeps the glow from flickering on colors close to the threshold
+    frag_color = vec4(color.rgb*smoothstep(threshold, threshold + 0.1f, luminance), color.a);
+}
@@ -0,0 +1,43 @@
+#version 450
+
+// Post effect that maps every color through a lookup table texture
+// The table is 256x16 texels: 16 slices of 16x16 side by side, one for every step of blue
+// Within a slice red goes to the right and green goes down, like in most lookup table exports
+
+layout (location=0) in vec2 uv;
+
+layout (location=0) out vec4 frag_color;
+
+layout (set=0, binding=0) uniform sampler post_sampler;
+layout (set=0, binding=1) uniform texture2D previous;
+
+layout (set=1, binding=0) uniform texture2DArray textures;
+
+layout (set=2, binding=0) uniform ColorGrading {
+    // Region of the table in the texture array, see texture::TextureRegion
+    vec2 lut_offset;
+    vec2 lut_scale;
+    int lut_layer;
+};
+
+// Tables are made for srgb colors, but the frame is sampled in linear space
+vec3 linear_to_srgb(vec3 color) {
+    return mix(color*12.92f, 1.055f*pow(color, vec3(1.0f/2.4f)) - 0.055f, step(vec3(0.0031308f), color));
+}
+
+vec3 sample_slice(vec2 texel, float slice) {
+    vec2 lut_uv = texel + vec2(slice/16.0f, 0.0f);
+    return texture(sampler2DArray(textures, post_sampler), vec3(lut_offset + lut_uv*lut_scale, lut_layer)).rgb;
+}
+
+void main() {
+    vec4 color = texture(sampler2D(previous, post_sampler), uv);
+    vec3 srgb = linear_to_srgb(clamp(color.rgb, 0.0f, 1.0f));
+
+    float blue = srgb.b*15.0f;
+    float slice = floor(blue);
+    // The centers of the texels, so the neighbouring slice doesn't bleed in
+    vec2 texel = (srgb.rg*15.0f + 0.5f)/vec2(256.0f, 16.0f);
+    vec3 graded = mix(sample_slice(texel, slice), sample_slice(texel, min(slice + 1.0f, 15.0f)), blue - slice);
+    frag_color = vec4(graded, color.a);
+}
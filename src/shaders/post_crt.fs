@@ -0,0 +1,31 @@
+#version 450
+
+// Post effect that imitates a crt monitor with dark scanlines and columns of red, green and blue phosphors
+
+layout (location=0) in vec2 uv;
+
+layout (location=0) out vec4 frag_color;
+
+layout (set=0, binding=0) uniform sampler post_sampler;
+layout (set=0, binding=1) uniform texture2D previous;
+layout (set=0, binding=3) uniform PostUniforms {
+    vec2 texel_size;
+};
+
+layout (set=2, binding=0) uniform Crt {
+    // 0 leaves the frame as it is, 1 makes every other row black
+    float scanline_strength;
+};
+
+void main() {
+    vec4 color = texture(sampler2D(previous, post_sampler), uv);
+    vec2 pixel = floor(uv/texel_size);
+
+    float scanline = mod(pixel.y, 2.0f);
+    int phosphor = int(mod(pixel.x, 3.0f));
+    vec3 mask = vec3(0.7f);
+    mask[phosphor] = 1.0f;
+
+    color.rgb *= mix(vec3(1.0f), mask*(1.0f - scanline), scanline_strength);
+    frag_color = color;
+}
@@ -0,0 +1,33 @@
+#version 450
+
+// Post effect that darkens the corners of the frame
+// Every post effect's fragment shader can use these inputs, custom ones included
+
+layout (location=0) in vec2 uv;
+
+layout (location=0) out vec4 frag_color;
+
+layout (set=0, binding=0) uniform sampler post_sampler;
+// What the pass before this one rendered, the frame itself for the first pass
+layout (set=0, binding=1) uniform texture2D previous;
+// What the effect this pass belongs to started with, effects with a single pass get the same texture as previous
+layout (set=0, binding=2) uniform texture2D effect_input;
+layout (set=0, binding=3) uniform PostUniforms {
+    vec2 texel_size;
+};
+
+// Every loaded texture and render target, like in shader.fs
+layout (set=1, binding=0) uniform texture2DArray textures;
+
+// Uniforms of the effect are always set 2, binding 0
+layout (set=2, binding=0) uniform Vignette {
+    float strength;
+};
+
+void main() {
+    vec4 color = texture(sampler2D(previous, post_sampler), uv);
+    // 0 in the center and 1 in the corners
+    float distance = length(uv - 0.5f)*sqrt(2.0f);
+    color.rgb *= 1.0f - strength*smoothstep(0.4f, 1.0f, distance);
+    frag_color = color;
+}
@@ -3,10 +3,24 @@
 layout (location=0) in vec2 v_position;
 layout (location=1) in int v_texture_binding;
 layout (location=2) in vec4 v_tex_coords_or_color;
+layout (location=3) in uint v_sampler_index;
+layout (location=4) in uint v_transform_id;
+
+// Columns of the model matrix, see instance.rs
+layout (location=5) in vec4 i_transform_0;
+layout (location=6) in vec4 i_transform_1;
+layout (location=7) in vec4 i_transform_2;
+layout (location=8) in vec4 i_tint;
+// Texture region of instanced shapes, a layer of -1 keeps the vertex's own
+layout (location=9) in vec2 i_uv_offset;
+layout (location=10) in vec2 i_uv_scale;
+layout (location=11) in int i_texture_layer;
 
 layout (location=0) out VS_OUT {
     flat int texture_binding;
     vec4 tex_coords_or_color;
+    flat uint sampler_index;
+    flat vec4 tint;
 } vs_out;
 
 
@@ -20,8 +34,24 @@ layout(set=0, binding=0) uniform Uniforms {
     mat4 camera_transform;
 };
 
+// The transforms of the draws in the current batch, 3 padded columns each like the instance transform
+// Transform 0 is the identity
+layout(std430, set=0, binding=1) readonly buffer Transforms {
+    vec4 transform_columns[];
+};
+
 void main () {
-    gl_Position = vec4((mat3(camera_transform)*vec3(v_position,1.0f)).xy, 0.0f, 1.0f);
-    vs_out.texture_binding = v_texture_binding;
-    vs_out.tex_coords_or_color = v_tex_coords_or_color;
+    mat3 model_transform = mat3(i_transform_0.xyz, i_transform_1.xyz, i_transform_2.xyz);
+    uint column = 3*v_transform_id;
+    mat3 draw_transform = mat3(transform_columns[column].xyz, transform_columns[column+1].xyz, transform_columns[column+2].xyz);
+    gl_Position = vec4((mat3(camera_transform)*model_transform*draw_transform*vec3(v_position,1.0f)).xy, 0.0f, 1.0f);
+    if (v_texture_binding >= 0 && i_texture_layer >= 0) {
+        vs_out.texture_binding = i_texture_layer;
+        vs_out.tex_coords_or_color = vec4(i_uv_offset + v_tex_coords_or_color.xy*i_uv_scale, v_tex_coords_or_color.zw);
+    } else {
+        vs_out.texture_binding = v_texture_binding;
+        vs_out.tex_coords_or_color = v_tex_coords_or_color;
+    }
+    vs_out.sampler_index = v_sampler_index;
+    vs_out.tint = i_tint;
 }
\ No newline at end of file
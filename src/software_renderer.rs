@@ -0,0 +1,1001 @@
+use super::vertex::Vertex;
+use super::instance::DrawInstance;
+use super::point::Point;
+use super::camera::*;
+use super::uniforms::Uniforms;
+use super::texture::Sampler;
+use super::blend_mode::BlendMode;
+use super::clip::{ClipStack, ClipState, ScissorRect};
+use super::viewport::Viewport;
+use super::renderer::{Drawable, ShapeRenderer, RendererError, PLACEHOLDER_TEXTURE_NAME};
+use super::mesh_store::{MeshStore, MeshHandle};
+
+use std::collections::{
+    HashMap,
+    HashSet,
+    hash_map,
+};
+
+/*
+A reference rasterizer that runs entirely on the cpu
+It follows the same rules as the gpu pipeline in renderer.rs so its output can be compared against it:
+ - Vertices are transformed by the UsableTransform and then by the camera matrix in Uniforms
+ - Pixels are covered if their center is inside a triangle (top-left fill rule)
+ - texture_binding is flat and taken from the first vertex of each triangle, tex_coords_or_color is interpolated
+ - Textures are sampled with the sampler of the first vertex, picked like in Renderer::draw
+ - Colors are blended with the equation of the current BlendMode
+ - Draws are queued and drawn from the lowest to the highest layer by end_render
+ - Pixels outside the scissor rectangle or outside any clip mask are not drawn
+ - Vertices are mapped into the current viewport when they are queued, pixels outside of it are not drawn
+ - Clips are placed when they are pushed: scissor rectangles relative to the viewport, masks with the camera and viewport
+ - Render targets are framebuffers of their own that are stored as textures when they are finished
+ - Instanced shapes are drawn once per instance, their colors are multiplied with the instance's tint
+ - Materials are ignored since their GLSL shaders can't run here, everything is drawn like the default material
+ - There are no post effects for the same reason, frames are stored as they were drawn
+ - The framebuffer is stored as 8 bit srgb like a Rgba8UnormSrgb target; blending happens in linear space
+*/
+struct QueuedDraw {
+    layer: i32,
+    blend_mode: BlendMode,
+    clip: usize,
+    viewport: Option<Viewport>,
+    texture_name: String,
+    tint: [f32; 4],
+    indices: Vec<u32>,
+    // In pixel space
+    vertices: Vec<Vertex>,
+}
+
+// A copy of a shape made by create_mesh, in world space
+struct Mesh {
+    texture_name: String,
+    // Only textured meshes can be drawn with another texture
+    textured: bool,
+    indices: Vec<u32>,
+    vertices: Vec<Vertex>,
+}
+
+// In pixel space
+struct ClipMask {
+    indices: Vec<u32>,
+    vertices: Vec<Vertex>,
+    // The viewport it was pushed in, the gpu clips the mask's triangles to it
+    bounds: ScissorRect,
+}
+
+pub struct SoftwareRenderer {
+    width: u32,
+    height: u32,
+    framebuffer: image::RgbaImage,
+
+    loaded_textures: HashMap<String, image::RgbaImage>,
+    texture_samplers: HashMap<String, Sampler>,
+    sampler_override: Option<Sampler>,
+    // Their names are also in loaded_textures
+    render_targets: HashSet<String>,
+    // Like RendererDescriptor::texture_layer_size, render targets can't be bigger
+    texture_layer_size: u32,
+    // Like in Renderer, targets used since the last frame ended can't be rendered until it ends
+    current_target: Option<String>,
+    sampled_targets: HashSet<String>,
+    blend_mode: BlendMode,
+    layer: i32,
+    draw_queue: Vec<QueuedDraw>,
+    meshes: MeshStore<Mesh>,
+    clip_stack: ClipStack,
+    clip_masks: Vec<ClipMask>,
+    viewport: Option<Viewport>,
+
+    uniforms: Uniforms,
+
+    clear_color: [f32; 4],
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> u8 {
+    let value = value.clamp(0.0, 1.0);
+    let value = if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0/2.4) - 0.055
+    };
+    (value * 255.0).round() as u8
+}
+
+fn unorm_to_u8(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+// Twice the signed area of the triangle a, b, p
+fn edge_function(a: Point, b: Point, p: Point) -> f32 {
+    (b.x-a.x)*(p.y-a.y) - (b.y-a.y)*(p.x-a.x)
+}
+
+// With a positive area in screen space (y pointing down), top edges are horizontal and go right and left edges go up
+fn is_top_left_edge(a: Point, b: Point) -> bool {
+    (a.y == b.y && b.x > a.x) || b.y < a.y
+}
+
+impl SoftwareRenderer {
+    pub fn new(width: u32, height: u32, texture_layer_size: u32) -> Self {
+        let placeholder_texture_bytes = include_bytes!("textures/placeholder_texture.png");
+        let placeholder_texture = image::load_from_memory(placeholder_texture_bytes).unwrap().to_rgba();
+
+        let mut loaded_textures = HashMap::new();
+        loaded_textures.insert(String::from(PLACEHOLDER_TEXTURE_NAME), placeholder_texture);
+
+        Self {
+            width,
+            height,
+            framebuffer: image::RgbaImage::new(width, height),
+
+            loaded_textures,
+            texture_samplers: HashMap::new(),
+            sampler_override: None,
+            render_targets: HashSet::new(),
+            texture_layer_size,
+            current_target: None,
+            sampled_targets: HashSet::new(),
+            blend_mode: BlendMode::Normal,
+            layer: 0,
+            draw_queue: vec![],
+            meshes: MeshStore::new(),
+            clip_stack: ClipStack::new(),
+            clip_masks: vec![],
+            viewport: None,
+
+            uniforms: Uniforms::new(),
+
+            clear_color: [0.1, 0.2, 0.3, 0.0],
+        }
+    }
+
+    pub fn update(&mut self, camera: &Camera) {
+        self.uniforms.set_camera(camera);
+    }
+
+    // Returns false if a texture with this label is already loaded
+    pub fn load_texture(&mut self, bytes:&[u8], label: String) -> Result<bool, RendererError> {
+        let texture = image::load_from_memory(bytes)?.to_rgba();
+
+        let entry = self.loaded_textures.entry(label);
+        match entry {
+            hash_map::Entry::Occupied(..) => Ok(false),
+            hash_map::Entry::Vacant(..) => {
+                entry.or_insert(texture);
+                Ok(true)
+            },
+        }
+    }
+
+    // Like Renderer::create_render_target the target starts out transparent and can't be bigger than a texture layer
+    // Returns false if a texture with this name is already loaded
+    pub fn create_render_target(&mut self, name: &str, width: u32, height: u32) -> Result<bool, RendererError> {
+        if self.loaded_textures.contains_key(name) {
+            return Ok(false);
+        }
+        let layer_size = self.texture_layer_size;
+        if width == 0 || height == 0 || width > layer_size || height > layer_size {
+            return Err(RendererError::InvalidRenderTargetSize { width, height, layer_size });
+        }
+        self.loaded_textures.insert(String::from(name), image::RgbaImage::new(width, height));
+        self.render_targets.insert(String::from(name));
+        Ok(true)
+    }
+
+    // Renders into a framebuffer of the target's size, which replaces the target's texture when it is done
+    // Fails like Renderer::render_to_target if an earlier pass of the frame used the target
+    pub fn render_to_target<F: FnOnce(&mut Self)>(&mut self, name: &str, draw_fn: F) -> Result<(), RendererError> {
+        if self.sampled_targets.contains(name) {
+            return Err(RendererError::TargetSampledBeforeRender(String::from(name)));
+        }
+        let (width, height) = match self.loaded_textures.get(name) {
+            Some(texture) if self.render_targets.contains(name) => texture.dimensions(),
+            _ => return Err(RendererError::UnknownRenderTarget(String::from(name))),
+        };
+        let framebuffer = std::mem::replace(&mut self.framebuffer, image::RgbaImage::new(width, height));
+        let frame_size = (self.width, self.height);
+        self.width = width;
+        self.height = height;
+
+        // Like in Renderer::render_to_target, draws queued outside of a frame wait for the next frame
+        let queued_draws = std::mem::take(&mut self.draw_queue);
+        let clip_stack = std::mem::replace(&mut self.clip_stack, ClipStack::new());
+        let clip_masks = std::mem::take(&mut self.clip_masks);
+        let viewport = self.viewport.take();
+        self.current_target = Some(String::from(name));
+        self.begin_render();
+        draw_fn(self);
+        self.end_render();
+        self.current_target = None;
+        self.draw_queue = queued_draws;
+        self.clip_stack = clip_stack;
+        self.clip_masks = clip_masks;
+        self.viewport = viewport;
+
+        let target = std::mem::replace(&mut self.framebuffer, framebuffer);
+        self.width = frame_size.0;
+        self.height = frame_size.1;
+        self.loaded_textures.insert(String::from(name), target);
+        Ok(())
+    }
+
+    pub fn set_texture_sampler(&mut self, label: &str, sampler: Sampler) {
+        self.texture_samplers.insert(String::from(label), sampler);
+    }
+
+    pub fn set_sampler(&mut self, sampler: Option<Sampler>) {
+        self.sampler_override = sampler;
+    }
+
+    pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+        self.blend_mode = blend_mode;
+    }
+
+    pub fn set_layer(&mut self, layer: i32) {
+        self.layer = layer;
+    }
+
+    // Materials only exist on the gpu, see the comment at the top
+    pub fn set_material(&mut self, _name: Option<&str>) {}
+
+    // Like Renderer::set_viewport, following draws and clip masks are mapped into the viewport
+    pub fn set_viewport(&mut self, viewport: Option<Viewport>) {
+        self.viewport = viewport;
+    }
+
+    // Like Renderer::push_scissor_rect, the rectangle is relative to the current viewport
+    pub fn push_scissor_rect(&mut self, rect: ScissorRect) {
+        let rect = match self.viewport {
+            Some(viewport) => viewport.to_frame_rect(rect),
+            None => rect,
+        };
+        self.clip_stack.push_scissor(rect);
+    }
+
+    pub fn push_clip_mask<'a, T:Drawable<'a>>(&mut self, shape: &'a T, transformation: Option<&UsableTransform>) {
+        let (shape_indices, shape_vertices) = shape.get_vertex_information();
+        let vertices = self.to_pixel_space(shape_vertices, transformation);
+        let bounds = self.viewport.unwrap_or(Viewport { x: 0, y: 0, width: self.width, height: self.height }).to_scissor_rect();
+
+        self.clip_masks.push(ClipMask {
+            indices: shape_indices.to_offset_vec(0),
+            vertices,
+            bounds,
+        });
+        self.clip_stack.push_mask(self.clip_masks.len() - 1);
+    }
+
+    // Like Renderer::pop_clip, popping without a pushed clip fails
+    pub fn pop_clip(&mut self) -> Result<(), RendererError> {
+        if self.clip_stack.pop() {
+            Ok(())
+        } else {
+            Err(RendererError::NoClipToPop)
+        }
+    }
+
+    pub fn begin_render(&mut self) {
+        let clear_pixel = image::Rgba([
+            linear_to_srgb(self.clear_color[0]),
+            linear_to_srgb(self.clear_color[1]),
+            linear_to_srgb(self.clear_color[2]),
+            unorm_to_u8(self.clear_color[3]),
+        ]);
+        for pixel in self.framebuffer.pixels_mut() {
+            *pixel = clear_pixel;
+        }
+    }
+
+    pub fn end_render(&mut self) -> &image::RgbaImage {
+        let mut draw_queue = std::mem::take(&mut self.draw_queue);
+        // Stable like in Renderer::flush_draw_queue
+        draw_queue.sort_by_key(|queued_draw| queued_draw.layer);
+        // Coverage of every clip state and viewport that is used, None when nothing is clipped
+        let mut clip_coverages: HashMap<(usize, Option<Viewport>), Option<Vec<bool>>> = HashMap::new();
+        for queued_draw in &draw_queue {
+            let clip = clip_coverages.entry((queued_draw.clip, queued_draw.viewport)).or_insert_with(|| {
+                let clip_state = self.clip_stack.get(queued_draw.clip);
+                if *clip_state == ClipState::default() && queued_draw.viewport.is_none() {
+                    None
+                } else {
+                    Some(self.create_clip_coverage(clip_state, queued_draw.viewport))
+                }
+            }).as_deref();
+
+            let texture = match self.loaded_textures.get(&queued_draw.texture_name) {
+                Some(tex) => tex,
+                None => self.loaded_textures.get(PLACEHOLDER_TEXTURE_NAME).unwrap(),
+            };
+
+            for triangle in queued_draw.indices.chunks_exact(3) {
+                Self::rasterize_triangle(
+                    &mut self.framebuffer,
+                    texture,
+                    queued_draw.blend_mode,
+                    queued_draw.tint,
+                    clip,
+                    [
+                        queued_draw.vertices[triangle[0] as usize],
+                        queued_draw.vertices[triangle[1] as usize],
+                        queued_draw.vertices[triangle[2] as usize],
+                    ],
+                );
+            }
+        }
+
+        // Clips and viewports only last for one frame
+        self.clip_stack.reset();
+        self.clip_masks.clear();
+        self.viewport = None;
+        if self.current_target.is_none() {
+            self.sampled_targets.clear();
+        }
+        &self.framebuffer
+    }
+
+    // Renders everything drawn in draw_fn and returns a copy of the resulting pixels
+    pub fn render<F: FnOnce(&mut Self)>(&mut self, draw_fn: F) -> image::RgbaImage {
+        self.begin_render();
+        draw_fn(self);
+        self.end_render().clone()
+    }
+
+    // Goes from world space to pixel space, inside the viewport if there is one
+    fn to_pixel_space(&self, vertices: &[Vertex], transformation: Option<&UsableTransform>) -> Vec<Vertex> {
+        let mut matrix = self.uniforms.get_camera_transform();
+        if let Some(transf) = transformation {
+            matrix = matrix * transf.get_transformation_matrix();
+        }
+
+        let viewport = self.viewport.unwrap_or(Viewport { x: 0, y: 0, width: self.width, height: self.height });
+        let mut vertices: Vec<Vertex> = vertices.to_vec();
+        for vertex in &mut vertices {
+            UsableTransform::transform_point_with_matrix(&mut vertex.position, &matrix);
+            vertex.position.x = viewport.x as f32 + (vertex.position.x + 1.0) / 2.0 * viewport.width as f32;
+            vertex.position.y = viewport.y as f32 + (1.0 - vertex.position.y) / 2.0 * viewport.height as f32;
+        }
+        vertices
+    }
+
+    // Copies the vertices of the shape and returns them with the name of the texture they use
+    fn prepare_vertices<'a, T:Drawable<'a>>(&self, shape: &'a T) -> (String, Vec<Vertex>) {
+        let (_, shape_vertices) = shape.get_vertex_information();
+        let mut vertices = shape_vertices.to_vec();
+
+        // Like Renderer::draw, naming a texture overrides the texture binding of every vertex
+        let texture_name = shape.get_texture_name();
+        if texture_name.is_some() {
+            for vertex in &mut vertices {
+                vertex.texture_binding = 0;
+            }
+        }
+        let sampler = match &texture_name {
+            Some(name) => self.sampler_override.or_else(|| self.texture_samplers.get(name).copied()),
+            None => self.sampler_override,
+        };
+        if let Some(sampler) = sampler {
+            for vertex in &mut vertices {
+                vertex.sampler = sampler as u32;
+            }
+        }
+        let texture_name = texture_name.unwrap_or_else(|| String::from(PLACEHOLDER_TEXTURE_NAME));
+        (texture_name, vertices)
+    }
+
+    // A target drawn into itself shows its previous contents, which is allowed
+    fn queue_draw(&mut self, queued_draw: QueuedDraw) {
+        let name = &queued_draw.texture_name;
+        if self.render_targets.contains(name) && self.current_target.as_ref() != Some(name) {
+            self.sampled_targets.insert(name.clone());
+        }
+        self.draw_queue.push(queued_draw);
+    }
+
+    pub fn draw<'a, T:Drawable<'a>>(&mut self, shape: &'a T, transformation: Option<&UsableTransform>) {
+        let (shape_indices, _) = shape.get_vertex_information();
+        let (texture_name, vertices) = self.prepare_vertices(shape);
+        let vertices = self.to_pixel_space(&vertices, transformation);
+
+        self.queue_draw(QueuedDraw {
+            layer: self.layer,
+            blend_mode: self.blend_mode,
+            clip: self.clip_stack.current(),
+            viewport: self.viewport,
+            texture_name,
+            tint: [1.0, 1.0, 1.0, 1.0],
+            indices: shape_indices.to_offset_vec(0),
+            vertices,
+        });
+    }
+
+    // Like Renderer::draw_instanced, the instances of a textured shape can replace its texture but not its sampler
+    pub fn draw_instanced<'a, T:Drawable<'a>>(&mut self, shape: &'a T, instances: &[DrawInstance]) {
+        let (shape_indices, _) = shape.get_vertex_information();
+        let (texture_name, vertices) = self.prepare_vertices(shape);
+        let textured = shape.get_texture_name().is_some();
+        for instance in instances {
+            let texture_name = match instance.texture_name {
+                Some(name) if textured => String::from(name),
+                _ => texture_name.clone(),
+            };
+            self.queue_draw(QueuedDraw {
+                layer: self.layer,
+                blend_mode: self.blend_mode,
+                clip: self.clip_stack.current(),
+                viewport: self.viewport,
+                texture_name,
+                tint: instance.tint,
+                indices: shape_indices.to_offset_vec(0),
+                vertices: self.to_pixel_space(&vertices, Some(&instance.transform)),
+            });
+        }
+    }
+
+    // Like Renderer::create_mesh the texture's sampler is looked up now
+    pub fn create_mesh<'a, T:Drawable<'a>>(&mut self, shape: &'a T) -> MeshHandle {
+        let (shape_indices, _) = shape.get_vertex_information();
+        let (texture_name, vertices) = self.prepare_vertices(shape);
+        self.meshes.insert(Mesh {
+            texture_name,
+            textured: shape.get_texture_name().is_some(),
+            indices: shape_indices.to_offset_vec(0),
+            vertices,
+        })
+    }
+
+    #[allow(dead_code)]
+    pub fn remove_mesh(&mut self, mesh: MeshHandle) -> Result<(), RendererError> {
+        match self.meshes.remove(mesh) {
+            Some(_) => Ok(()),
+            None => Err(RendererError::UnknownMesh),
+        }
+    }
+
+    // There is no gpu to keep the mesh on, so it is drawn like any other shape
+    // Like Renderer::draw_mesh the texture name only replaces the texture of textured meshes
+    pub fn draw_mesh(&mut self, mesh: MeshHandle, transformation: Option<&UsableTransform>, texture_name: Option<&str>) {
+        let queued_draw = match self.meshes.get(mesh) {
+            Some(mesh) => QueuedDraw {
+                layer: self.layer,
+                blend_mode: self.blend_mode,
+                clip: self.clip_stack.current(),
+                viewport: self.viewport,
+                texture_name: match texture_name {
+                    Some(name) if mesh.textured => String::from(name),
+                    _ => mesh.texture_name.clone(),
+                },
+                tint: [1.0, 1.0, 1.0, 1.0],
+                indices: mesh.indices.clone(),
+                vertices: self.to_pixel_space(&mesh.vertices, transformation),
+            },
+            None => return,
+        };
+        self.queue_draw(queued_draw);
+    }
+
+    // Calls pixel_fn with the position and barycentric coordinates of every pixel the triangle covers
+    fn for_each_covered_pixel<F: FnMut(u32, u32, [f32; 3])>(width: u32, height: u32, triangle: [Point; 3], mut pixel_fn: F) {
+        let v0 = triangle[0];
+        let mut v1 = triangle[1];
+        let mut v2 = triangle[2];
+
+        let mut area = edge_function(v0, v1, v2);
+        if area == 0.0 {
+            return;
+        }
+        // Nothing is culled so make every triangle have the same winding
+        let swapped = area < 0.0;
+        if swapped {
+            std::mem::swap(&mut v1, &mut v2);
+            area = -area;
+        }
+
+        let min_x = v0.x.min(v1.x).min(v2.x).floor().clamp(0.0, width as f32) as u32;
+        let min_y = v0.y.min(v1.y).min(v2.y).floor().clamp(0.0, height as f32) as u32;
+        let max_x = v0.x.max(v1.x).max(v2.x).ceil().clamp(0.0, width as f32) as u32;
+        let max_y = v0.y.max(v1.y).max(v2.y).ceil().clamp(0.0, height as f32) as u32;
+
+        let top_left_12 = is_top_left_edge(v1, v2);
+        let top_left_20 = is_top_left_edge(v2, v0);
+        let top_left_01 = is_top_left_edge(v0, v1);
+
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let p = Point{x: x as f32 + 0.5, y: y as f32 + 0.5};
+                let w0 = edge_function(v1, v2, p);
+                let w1 = edge_function(v2, v0, p);
+                let w2 = edge_function(v0, v1, p);
+
+                let covered =
+                    (w0 > 0.0 || (w0 == 0.0 && top_left_12)) &&
+                    (w1 > 0.0 || (w1 == 0.0 && top_left_20)) &&
+                    (w2 > 0.0 || (w2 == 0.0 && top_left_01));
+                if !covered {
+                    continue;
+                }
+
+                if swapped {
+                    pixel_fn(x, y, [w0/area, w2/area, w1/area]);
+                } else {
+                    pixel_fn(x, y, [w0/area, w1/area, w2/area]);
+                }
+            }
+        }
+    }
+
+    // clip has one entry per pixel, only pixels where it is true are drawn
+    fn rasterize_triangle(framebuffer: &mut image::RgbaImage, texture: &image::RgbaImage, blend_mode: BlendMode, tint: [f32; 4], clip: Option<&[bool]>, triangle: [Vertex; 3]) {
+        // Flat attributes come from the first (provoking) vertex
+        let texture_binding = triangle[0].texture_binding;
+        let sampler = Sampler::from_binding(triangle[0].sampler);
+
+        let (width, height) = framebuffer.dimensions();
+        let positions = [triangle[0].position, triangle[1].position, triangle[2].position];
+        Self::for_each_covered_pixel(width, height, positions, |x, y, barycentric| {
+            if let Some(clip) = clip {
+                if !clip[(y*width + x) as usize] {
+                    return;
+                }
+            }
+
+            let mut attribute = [0.0; 4];
+            for (i, value) in attribute.iter_mut().enumerate() {
+                *value =
+                    barycentric[0]*triangle[0].tex_coords_or_color[i] +
+                    barycentric[1]*triangle[1].tex_coords_or_color[i] +
+                    barycentric[2]*triangle[2].tex_coords_or_color[i];
+            }
+
+            let mut color = if texture_binding < 0 {
+                attribute
+            } else {
+                Self::sample_texture(texture, sampler, attribute[0], attribute[1])
+            };
+            for (value, tint) in color.iter_mut().zip(tint.iter()) {
+                *value *= tint;
+            }
+
+            Self::blend(framebuffer.get_pixel_mut(x, y), color, blend_mode);
+        });
+    }
+
+    // Pixels that are inside the viewport, the scissor rectangle and all masks of the clip state
+    fn create_clip_coverage(&self, clip_state: &ClipState, viewport: Option<Viewport>) -> Vec<bool> {
+        let mut coverage = vec![true; (self.width*self.height) as usize];
+        let scissor = match (clip_state.scissor, viewport) {
+            (Some(scissor), Some(viewport)) => Some(scissor.intersect(viewport.to_scissor_rect())),
+            (scissor, viewport) => scissor.or_else(|| viewport.map(Viewport::to_scissor_rect)),
+        };
+        if let Some(scissor) = scissor {
+            for y in 0..self.height {
+                for x in 0..self.width {
+                    coverage[(y*self.width + x) as usize] = scissor.contains(x, y);
+                }
+            }
+        }
+        for mask in &clip_state.masks {
+            let clip_mask = &self.clip_masks[*mask];
+            let mut mask_coverage = vec![false; coverage.len()];
+            for triangle in clip_mask.indices.chunks_exact(3) {
+                let positions = [
+                    clip_mask.vertices[triangle[0] as usize].position,
+                    clip_mask.vertices[triangle[1] as usize].position,
+                    clip_mask.vertices[triangle[2] as usize].position,
+                ];
+                Self::for_each_covered_pixel(self.width, self.height, positions, |x, y, _| {
+                    mask_coverage[(y*self.width + x) as usize] = clip_mask.bounds.contains(x, y);
+                });
+            }
+            for (covered, inside_mask) in coverage.iter_mut().zip(mask_coverage) {
+                *covered = *covered && inside_mask;
+            }
+        }
+        coverage
+    }
+
+    // Maps a texel coordinate that might be outside of the texture back into it
+    fn wrap_texel(coordinate: i64, size: u32, address_mode: wgpu::AddressMode) -> u32 {
+        let size = size as i64;
+        let wrapped = match address_mode {
+            wgpu::AddressMode::Repeat => coordinate.rem_euclid(size),
+            wgpu::AddressMode::MirrorRepeat => {
+                let period = coordinate.rem_euclid(2*size);
+                if period < size { period } else { 2*size - 1 - period }
+            },
+            _ => coordinate.clamp(0, size-1),
+        };
+        wrapped as u32
+    }
+
+    // Returns linear color
+    fn sample_texture(texture: &image::RgbaImage, sampler: Sampler, u: f32, v: f32) -> [f32; 4] {
+        let (width, height) = texture.dimensions();
+        let address_mode = sampler.get_address_mode();
+
+        let texel = |tx: f32, ty: f32| -> [f32; 4] {
+            let tx = Self::wrap_texel(tx as i64, width, address_mode);
+            let ty = Self::wrap_texel(ty as i64, height, address_mode);
+            let pixel = texture.get_pixel(tx, ty);
+            [
+                srgb_to_linear(pixel[0]),
+                srgb_to_linear(pixel[1]),
+                srgb_to_linear(pixel[2]),
+                pixel[3] as f32 / 255.0,
+            ]
+        };
+
+        if sampler.get_filter_mode() == wgpu::FilterMode::Nearest {
+            return texel((u * width as f32).floor(), (v * height as f32).floor());
+        }
+
+        let x = u * width as f32 - 0.5;
+        let y = v * height as f32 - 0.5;
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let fx = x - x0;
+        let fy = y - y0;
+
+        let t00 = texel(x0, y0);
+        let t10 = texel(x0+1.0, y0);
+        let t01 = texel(x0, y0+1.0);
+        let t11 = texel(x0+1.0, y0+1.0);
+
+        let mut result = [0.0; 4];
+        for (i, value) in result.iter_mut().enumerate() {
+            let top = t00[i]*(1.0-fx) + t10[i]*fx;
+            let bottom = t01[i]*(1.0-fx) + t11[i]*fx;
+            *value = top*(1.0-fy) + bottom*fy;
+        }
+        result
+    }
+
+    // Same equations as BlendMode::get_color_blend and BlendMode::get_alpha_blend
+    fn blend(destination: &mut image::Rgba<u8>, source: [f32; 4], blend_mode: BlendMode) {
+        let mut src = [0.0; 4];
+        for (value, source) in src.iter_mut().zip(source.iter()) {
+            *value = source.clamp(0.0, 1.0);
+        }
+        let dst = [
+            srgb_to_linear(destination[0]),
+            srgb_to_linear(destination[1]),
+            srgb_to_linear(destination[2]),
+            destination[3] as f32 / 255.0,
+        ];
+        let src_alpha = src[3];
+        let dst_alpha = dst[3];
+
+        let mut color = [0.0; 3];
+        for (i, value) in color.iter_mut().enumerate() {
+            *value = match blend_mode {
+                BlendMode::Normal => src[i]*src_alpha + dst[i]*(1.0-src_alpha),
+                BlendMode::Additive => src[i]*src_alpha + dst[i],
+                BlendMode::Multiply => src[i]*dst[i],
+                BlendMode::Screen => src[i] + dst[i]*(1.0-src[i]),
+                BlendMode::PremultipliedAlpha => src[i] + dst[i]*(1.0-src_alpha),
+                BlendMode::Opaque => src[i],
+            };
+        }
+        let alpha = match blend_mode {
+            BlendMode::Normal | BlendMode::Opaque => src_alpha,
+            BlendMode::Additive => src_alpha + dst_alpha,
+            BlendMode::Multiply | BlendMode::Screen => dst_alpha,
+            BlendMode::PremultipliedAlpha => src_alpha + dst_alpha*(1.0-src_alpha),
+        };
+
+        *destination = image::Rgba([
+            linear_to_srgb(color[0]),
+            linear_to_srgb(color[1]),
+            linear_to_srgb(color[2]),
+            unorm_to_u8(alpha),
+        ]);
+    }
+}
+
+impl ShapeRenderer for SoftwareRenderer {
+    fn draw<'a, T:Drawable<'a>>(&mut self, shape: &'a T, transformation: Option<&UsableTransform>) {
+        SoftwareRenderer::draw(self, shape, transformation);
+    }
+
+    fn set_sampler(&mut self, sampler: Option<Sampler>) {
+        SoftwareRenderer::set_sampler(self, sampler);
+    }
+
+    fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+        SoftwareRenderer::set_blend_mode(self, blend_mode);
+    }
+
+    fn set_layer(&mut self, layer: i32) {
+        SoftwareRenderer::set_layer(self, layer);
+    }
+
+    fn create_mesh<'a, T:Drawable<'a>>(&mut self, shape: &'a T) -> MeshHandle {
+        SoftwareRenderer::create_mesh(self, shape)
+    }
+
+    fn draw_mesh(&mut self, mesh: MeshHandle, transformation: Option<&UsableTransform>, texture_name: Option<&str>) {
+        SoftwareRenderer::draw_mesh(self, mesh, transformation, texture_name);
+    }
+
+    fn draw_instanced<'a, T:Drawable<'a>>(&mut self, shape: &'a T, instances: &[DrawInstance]) {
+        SoftwareRenderer::draw_instanced(self, shape, instances);
+    }
+
+    fn set_material(&mut self, name: Option<&str>) {
+        SoftwareRenderer::set_material(self, name);
+    }
+
+    fn update(&mut self, camera: &Camera) {
+        SoftwareRenderer::update(self, camera);
+    }
+
+    fn set_viewport(&mut self, viewport: Option<Viewport>) {
+        SoftwareRenderer::set_viewport(self, viewport);
+    }
+
+    fn push_scissor_rect(&mut self, rect: ScissorRect) {
+        SoftwareRenderer::push_scissor_rect(self, rect);
+    }
+
+    fn push_clip_mask<'a, T:Drawable<'a>>(&mut self, shape: &'a T, transformation: Option<&UsableTransform>) {
+        SoftwareRenderer::push_clip_mask(self, shape, transformation);
+    }
+
+    fn pop_clip(&mut self) -> Result<(), RendererError> {
+        SoftwareRenderer::pop_clip(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::polygons::{Polygon, Rectangle};
+    use crate::objects::lines::LineStrip;
+    use crate::objects::bezier_curves::BezierCurve;
+
+    const RED: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+
+    // One string per row, # for pixels drawn in opaque red and . for pixels that still have the clear color
+    fn get_coverage(image: &image::RgbaImage) -> Vec<String> {
+        image.rows().map(|row| {
+            row.map(|pixel| match pixel.0 {
+                [255, 0, 0, 255] => '#',
+                [_, _, _, 0] => '.',
+                _ => '?',
+            }).collect()
+        }).collect()
+    }
+
+    fn render_coverage<'a, T: Drawable<'a>>(shape: &'a T, width: u32, height: u32) -> Vec<String> {
+        let mut renderer = SoftwareRenderer::new(width, height, 64);
+        let image = renderer.render(|renderer| renderer.draw(shape, None));
+        get_coverage(&image)
+    }
+
+    #[test]
+    fn removed_mesh_draws_nothing() {
+        let square = Polygon::new(
+            &[Point{x:-0.5, y:-0.5}, Point{x: 0.5, y:-0.5}, Point{x: 0.5, y: 0.5}, Point{x:-0.5, y: 0.5}],
+            None, Some(&RED),
+        );
+        let mut renderer = SoftwareRenderer::new(4, 4, 64);
+        let mesh = renderer.create_mesh(&square);
+        assert!(renderer.remove_mesh(mesh).is_ok());
+        assert!(renderer.remove_mesh(mesh).is_err());
+        // The new mesh reuses the slot, the old handle still draws nothing
+        let new_mesh = renderer.create_mesh(&square);
+        let image = renderer.render(|renderer| renderer.draw_mesh(mesh, None, None));
+        assert_eq!(get_coverage(&image), ["....", "....", "....", "...."]);
+        let image = renderer.render(|renderer| renderer.draw_mesh(new_mesh, None, None));
+        assert_eq!(get_coverage(&image), ["....", ".##.", ".##.", "...."]);
+    }
+
+    #[test]
+    fn mesh_texture_can_be_replaced() {
+        let mut renderer = SoftwareRenderer::new(16, 16, 64);
+        renderer.load_texture(include_bytes!("textures/happy-tree.png"), String::from("happy-tree.png")).unwrap();
+        renderer.load_texture(include_bytes!("textures/awesomeface_with_transparency.png"), String::from("awesomeface.png")).unwrap();
+        let tree = Rectangle::new(Point{x:-1.0, y: 1.0}, Point{x: 1.0, y:-1.0}, Some(String::from("happy-tree.png")), None);
+        let face = Rectangle::new(Point{x:-1.0, y: 1.0}, Point{x: 1.0, y:-1.0}, Some(String::from("awesomeface.png")), None);
+        let mesh = renderer.create_mesh(&tree);
+
+        let replaced = renderer.render(|renderer| renderer.draw_mesh(mesh, None, Some("awesomeface.png")));
+        let expected = renderer.render(|renderer| renderer.draw(&face, None));
+        assert!(*replaced == *expected);
+        let kept = renderer.render(|renderer| renderer.draw_mesh(mesh, None, None));
+        assert!(*kept != *expected);
+    }
+
+    #[test]
+    fn scissor_rect_is_relative_to_viewport() {
+        let square = Rectangle::new(Point{x:-1.0, y: 1.0}, Point{x: 1.0, y:-1.0}, None, Some(&RED));
+        let mut renderer = SoftwareRenderer::new(8, 4, 64);
+        let image = renderer.render(|renderer| {
+            renderer.set_viewport(Some(Viewport { x: 4, y: 0, width: 4, height: 4 }));
+            renderer.push_scissor_rect(ScissorRect { x: 0, y: 1, width: 2, height: 2 });
+            renderer.draw(&square, None);
+            renderer.pop_clip().unwrap();
+        });
+        assert_eq!(get_coverage(&image), ["........", "....##..", "....##..", "........"]);
+    }
+
+    #[test]
+    fn unbalanced_pop_clip_fails() {
+        let mut renderer = SoftwareRenderer::new(4, 4, 64);
+        renderer.push_scissor_rect(ScissorRect { x: 0, y: 0, width: 2, height: 2 });
+        assert!(renderer.pop_clip().is_ok());
+        match renderer.pop_clip() {
+            Err(RendererError::NoClipToPop) => {},
+            result => panic!("{:?}", result),
+        }
+    }
+
+    #[test]
+    fn clip_mask_keeps_viewport_it_was_pushed_in() {
+        let square = Rectangle::new(Point{x:-1.0, y: 1.0}, Point{x: 1.0, y:-1.0}, None, Some(&RED));
+        let mut renderer = SoftwareRenderer::new(8, 4, 64);
+        let image = renderer.render(|renderer| {
+            // The mask covers the whole left viewport but nothing outside of it
+            renderer.set_viewport(Some(Viewport { x: 0, y: 0, width: 6, height: 4 }));
+            renderer.push_clip_mask(&square, Some(&UsableTransform {
+                translation: cgmath::vec2(0.0, 0.0),
+                scale: cgmath::vec2(2.0, 2.0),
+                rotation: 0.0,
+            }));
+            renderer.set_viewport(Some(Viewport { x: 4, y: 0, width: 4, height: 4 }));
+            renderer.draw(&square, None);
+            renderer.pop_clip().unwrap();
+        });
+        assert_eq!(get_coverage(&image), ["....##..", "....##..", "....##..", "....##.."]);
+    }
+
+    fn create_target_rectangle(name: &str) -> Rectangle {
+        Rectangle::new(Point{x:-1.0, y: 1.0}, Point{x: 1.0, y:-1.0}, Some(String::from(name)), None)
+    }
+
+    #[test]
+    fn target_rendered_after_it_was_used_fails() {
+        let mut renderer = SoftwareRenderer::new(4, 4, 64);
+        renderer.create_render_target("a", 4, 4).unwrap();
+        renderer.create_render_target("b", 4, 4).unwrap();
+        let a_rectangle = create_target_rectangle("a");
+        // b shows a, so a has to be rendered first
+        assert!(renderer.render_to_target("b", |renderer| renderer.draw(&a_rectangle, None)).is_ok());
+        match renderer.render_to_target("a", |_| {}) {
+            Err(RendererError::TargetSampledBeforeRender(name)) => assert_eq!(name, "a"),
+            _ => panic!("a was rendered after b used it"),
+        }
+        // The next frame starts over
+        renderer.render(|_| {});
+        assert!(renderer.render_to_target("a", |_| {}).is_ok());
+        assert!(renderer.render_to_target("b", |renderer| renderer.draw(&a_rectangle, None)).is_ok());
+    }
+
+    #[test]
+    fn target_can_use_itself() {
+        let mut renderer = SoftwareRenderer::new(4, 4, 64);
+        renderer.create_render_target("a", 4, 4).unwrap();
+        let a_rectangle = create_target_rectangle("a");
+        assert!(renderer.render_to_target("a", |renderer| renderer.draw(&a_rectangle, None)).is_ok());
+        assert!(renderer.render_to_target("a", |renderer| renderer.draw(&a_rectangle, None)).is_ok());
+    }
+
+    #[test]
+    fn render_target_size_is_checked() {
+        let mut renderer = SoftwareRenderer::new(4, 4, 64);
+        for (width, height) in [(0, 0), (0, 4), (65, 4), (4, 65)].iter().copied() {
+            match renderer.create_render_target("a", width, height) {
+                Err(RendererError::InvalidRenderTargetSize { layer_size: 64, .. }) => {},
+                result => panic!("{}x{}: {:?}", width, height, result),
+            }
+        }
+        assert!(renderer.create_render_target("a", 64, 64).unwrap());
+        assert!(!renderer.create_render_target("a", 64, 64).unwrap());
+    }
+
+    #[test]
+    fn draw_before_target_pass_waits_for_frame() {
+        let square = Polygon::new(
+            &[Point{x:-0.5, y:-0.5}, Point{x: 0.5, y:-0.5}, Point{x: 0.5, y: 0.5}, Point{x:-0.5, y: 0.5}],
+            None, Some(&RED),
+        );
+        let mut renderer = SoftwareRenderer::new(4, 4, 64);
+        renderer.create_render_target("a", 4, 4).unwrap();
+        renderer.draw(&square, None);
+        assert!(renderer.render_to_target("a", |_| {}).is_ok());
+        assert_eq!(get_coverage(&renderer.loaded_textures["a"]), ["....", "....", "....", "...."]);
+        let image = renderer.render(|_| {});
+        assert_eq!(get_coverage(&image), ["....", ".##.", ".##.", "...."]);
+    }
+
+    #[test]
+    fn concave_polygon() {
+        let polygon = Polygon::new(
+            &[
+                Point{x:-0.5, y:-0.5}, Point{x: 0.5, y:-0.5}, Point{x: 0.5, y: 0.0},
+                Point{x: 0.0, y: 0.0}, Point{x: 0.0, y: 0.5}, Point{x:-0.5, y: 0.5},
+            ],
+            None, Some(&RED),
+        );
+        assert_eq!(render_coverage(&polygon, 8, 8), [
+            "........",
+            "........",
+            "..##....",
+            "..##....",
+            "..####..",
+            "..####..",
+            "........",
+            "........",
+        ]);
+    }
+
+    #[test]
+    fn line_strip() {
+        let line_strip = LineStrip::new(&[Point{x:-0.75, y: 0.0}, Point{x: 0.75, y: 0.0}], 0.5, None, Some(&RED));
+        assert_eq!(render_coverage(&line_strip, 8, 8), [
+            "........",
+            "........",
+            "........",
+            ".######.",
+            ".######.",
+            "........",
+            "........",
+            "........",
+        ]);
+    }
+
+    #[test]
+    fn line_strip_corner() {
+        // Segments are joined with a bevel, so the pixel on the outer corner stays empty
+        let line_strip = LineStrip::new(
+            &[Point{x:-0.75, y: 0.5}, Point{x: 0.5, y: 0.5}, Point{x: 0.5, y:-0.75}],
+            0.375, None, Some(&RED),
+        );
+        assert_eq!(render_coverage(&line_strip, 8, 8), [
+            "........",
+            ".#####..",
+            ".######.",
+            ".....##.",
+            ".....##.",
+            ".....##.",
+            ".....##.",
+            "........",
+        ]);
+    }
+
+    #[test]
+    fn straight_bezier_curve_matches_line() {
+        let points = [Point{x:-0.75, y: 0.0}, Point{x: 0.0, y: 0.0}, Point{x: 0.75, y: 0.0}];
+        let curve = BezierCurve::new(&points, 6, 0.5, None, Some(&RED));
+        let line_strip = LineStrip::new(&[points[0], points[2]], 0.5, None, Some(&RED));
+        assert_eq!(render_coverage(curve.get_line_strip(), 8, 8), render_coverage(&line_strip, 8, 8));
+    }
+
+    #[test]
+    fn bezier_curve() {
+        // The curve goes through (0, 0.25) halfway and bends back down to its end points
+        let curve = BezierCurve::new(
+            &[Point{x:-0.75, y:-0.5}, Point{x: 0.0, y: 1.0}, Point{x: 0.75, y:-0.5}],
+            30, 0.125, None, Some(&RED),
+        );
+        let coverage = render_coverage(curve.get_line_strip(), 16, 16);
+        let is_covered = |x: usize, y: usize| coverage[y].as_bytes()[x] == b'#';
+        // Pixel centers on the curve
+        assert!(is_covered(7, 6) && is_covered(8, 6));
+        assert!(is_covered(2, 11) && is_covered(13, 11));
+        // Below the top of the curve and above it
+        assert!(!is_covered(7, 9) && !is_covered(8, 9));
+        assert!(!is_covered(7, 3) && !is_covered(8, 3));
+        // Past the end points
+        assert!(!is_covered(0, 12) && !is_covered(15, 12));
+        assert!(coverage.iter().all(|row| !row.contains('?')));
+    }
+}
@@ -0,0 +1,213 @@
+use futures::FutureExt;
+use futures::executor::block_on;
+
+use std::future::Future;
+use std::pin::Pin;
+use std::ops::Range;
+
+// How many batches can be in flight before the cpu has to wait for the gpu
+pub const STAGING_BUFFER_COUNT: usize = 3;
+
+// Buffer copies have to start and end at multiples of this
+const COPY_ALIGNMENT: wgpu::BufferAddress = 4;
+
+// Rounds a size up so whole buffers can be copied
+pub fn get_aligned_size(size: wgpu::BufferAddress) -> wgpu::BufferAddress {
+    size.div_ceil(COPY_ALIGNMENT) * COPY_ALIGNMENT
+}
+
+type MappingFuture = Pin<Box<dyn Future<Output = Result<wgpu::BufferWriteMapping, wgpu::BufferAsyncErr>>>>;
+
+enum Mapping {
+    Mapped(wgpu::BufferWriteMapping),
+    // Mapping again once the gpu has copied the last batch out of it
+    Pending(MappingFuture),
+    Unmapped,
+}
+
+// A buffer the cpu writes a batch into, it is copied into a buffer on the gpu when the batch is rendered
+struct StagingBuffer {
+    buffer: wgpu::Buffer,
+    size: wgpu::BufferAddress,
+    mapping: Mapping,
+}
+
+impl StagingBuffer {
+    // A new buffer isn't used by the gpu, so mapping it doesn't wait for anything
+    fn new(device: &wgpu::Device, label: &str, size: wgpu::BufferAddress) -> Result<Self, wgpu::BufferAsyncErr> {
+        let size = get_aligned_size(size);
+        let buffer = device.create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some(label),
+                size,
+                usage: wgpu::BufferUsage::MAP_WRITE | wgpu::BufferUsage::COPY_SRC,
+            }
+        );
+        let future_write_mapping = buffer.map_write(0, size);
+        device.poll(wgpu::Maintain::Wait);
+        let write_mapping = block_on(future_write_mapping)?;
+        Ok(Self {
+            buffer,
+            size,
+            mapping: Mapping::Mapped(write_mapping),
+        })
+    }
+
+    fn request_mapping(&mut self) {
+        if let Mapping::Unmapped = self.mapping {
+            self.mapping = Mapping::Pending(Box::pin(self.buffer.map_write(0, self.size)));
+        }
+    }
+
+    // Only blocks if the gpu is still copying out of the buffer
+    fn get_mapping(&mut self, device: &wgpu::Device) -> Result<&mut [u8], wgpu::BufferAsyncErr> {
+        self.request_mapping();
+        if let Mapping::Pending(future) = &mut self.mapping {
+            device.poll(wgpu::Maintain::Poll);
+            let write_mapping = match future.as_mut().now_or_never() {
+                Some(write_mapping) => write_mapping?,
+                None => {
+                    device.poll(wgpu::Maintain::Wait);
+                    block_on(future.as_mut())?
+                },
+            };
+            self.mapping = Mapping::Mapped(write_mapping);
+        }
+        match &mut self.mapping {
+            Mapping::Mapped(write_mapping) => Ok(write_mapping.as_slice()),
+            // Mapped by now
+            _ => unreachable!(),
+        }
+    }
+}
+
+/*
+Streams batches to a buffer on the gpu through a ring of staging buffers
+The cpu fills the current staging buffer while the gpu copies out of the ones used by earlier batches,
+so rendering a batch only waits for the gpu when every staging buffer is still in use
+*/
+pub struct StagingRing {
+    label: &'static str,
+    buffers: Vec<StagingBuffer>,
+    current: usize,
+}
+
+impl StagingRing {
+    pub fn new(device: &wgpu::Device, label: &'static str, size: wgpu::BufferAddress) -> Result<Self, wgpu::BufferAsyncErr> {
+        let buffers = (0..STAGING_BUFFER_COUNT)
+            .map(|_| StagingBuffer::new(device, label, size))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            label,
+            buffers,
+            current: 0,
+        })
+    }
+
+    pub fn get_size(&self) -> wgpu::BufferAddress {
+        self.buffers[self.current].size
+    }
+
+    // The mapped contents of the current staging buffer
+    pub fn get_mapping(&mut self, device: &wgpu::Device) -> Result<&mut [u8], wgpu::BufferAsyncErr> {
+        self.buffers[self.current].get_mapping(device)
+    }
+
+    // Replaces the current staging buffer with a bigger one that starts with the bytes already written
+    // The other buffers grow when the ring gets to them
+    pub fn grow(&mut self, device: &wgpu::Device, size: wgpu::BufferAddress, used_bytes: usize) -> Result<(), wgpu::BufferAsyncErr> {
+        let mut buffer = StagingBuffer::new(device, self.label, size)?;
+        if used_bytes > 0 {
+            let old_mapping = self.buffers[self.current].get_mapping(device)?;
+            buffer.get_mapping(device)?[..used_bytes].copy_from_slice(&old_mapping[..used_bytes]);
+        }
+        self.buffers[self.current] = buffer;
+        Ok(())
+    }
+
+    // Unmaps the current staging buffer and copies the bytes into the target
+    // Nothing can be written into the staging buffer afterwards until the ring advances
+    // The copy is rounded up to an aligned size, so the target has to have room for that
+    pub fn copy_to(&mut self, encoder: &mut wgpu::CommandEncoder, bytes: Range<usize>, target: &wgpu::Buffer, target_offset: wgpu::BufferAddress) {
+        if bytes.is_empty() {
+            return;
+        }
+        let staging_buffer = &mut self.buffers[self.current];
+        // Dropping the mapping unmaps the buffer
+        staging_buffer.mapping = Mapping::Unmapped;
+        let size = get_aligned_size(bytes.len() as wgpu::BufferAddress);
+        encoder.copy_buffer_to_buffer(&staging_buffer.buffer, bytes.start as wgpu::BufferAddress, target, target_offset, size);
+    }
+
+    // Has to be called after the copy was submitted, the next batch is written into the next staging buffer
+    pub fn advance(&mut self, device: &wgpu::Device) -> Result<(), wgpu::BufferAsyncErr> {
+        let size = self.buffers[self.current].size;
+        self.buffers[self.current].request_mapping();
+        self.current = (self.current + 1) % self.buffers.len();
+        if self.buffers[self.current].size < size {
+            self.buffers[self.current] = StagingBuffer::new(device, self.label, size)?;
+        }
+        Ok(())
+    }
+}
+
+// A buffer on the gpu whose whole contents are written again every time a batch is rendered
+// It grows when the contents don't fit anymore
+pub struct StreamedBuffer {
+    label: &'static str,
+    usage: wgpu::BufferUsage,
+    buffer: wgpu::Buffer,
+    staging: StagingRing,
+}
+
+impl StreamedBuffer {
+    pub fn new(device: &wgpu::Device, label: &'static str, usage: wgpu::BufferUsage, size: wgpu::BufferAddress) -> Result<Self, wgpu::BufferAsyncErr> {
+        Ok(Self {
+            label,
+            usage,
+            buffer: create_target_buffer(device, label, usage, size),
+            staging: StagingRing::new(device, label, size)?,
+        })
+    }
+
+    pub fn get_buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    pub fn get_size(&self) -> wgpu::BufferAddress {
+        self.staging.get_size()
+    }
+
+    // write_fn fills the first size bytes, which are copied into the buffer by the encoder
+    // Returns true if the buffer was replaced by a bigger one, so bind groups using it have to be recreated
+    pub fn write<F: FnOnce(&mut [u8])>(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, size: usize, write_fn: F) -> Result<bool, wgpu::BufferAsyncErr> {
+        if size == 0 {
+            return Ok(false);
+        }
+        let needed_size = size as wgpu::BufferAddress;
+        let replaced = needed_size > self.get_size();
+        if replaced {
+            let new_size = needed_size.max(self.get_size().saturating_mul(2));
+            self.staging.grow(device, new_size, 0)?;
+            self.buffer = create_target_buffer(device, self.label, self.usage, new_size);
+        }
+        write_fn(&mut self.staging.get_mapping(device)?[..size]);
+        self.staging.copy_to(encoder, 0..size, &self.buffer, 0);
+        Ok(replaced)
+    }
+
+    // Has to be called after the encoder was submitted
+    pub fn advance(&mut self, device: &wgpu::Device) -> Result<(), wgpu::BufferAsyncErr> {
+        self.staging.advance(device)
+    }
+}
+
+pub fn create_target_buffer(device: &wgpu::Device, label: &str, usage: wgpu::BufferUsage, size: wgpu::BufferAddress) -> wgpu::Buffer {
+    device.create_buffer(
+        &wgpu::BufferDescriptor {
+            label: Some(label),
+            size: get_aligned_size(size),
+            usage: usage | wgpu::BufferUsage::COPY_DST,
+        }
+    )
+}
@@ -1,81 +1,276 @@
 use image::GenericImageView;
 
-pub struct Texture {
+// mod renderer;
+use super::renderer::RendererError;
+
+// Every loaded texture gets its own layer in one 2D texture array so a single draw call can use all of them
+// All layers have the same size; smaller images only cover part of their layer and are never resized
+// The array has the renderer's format so render targets can be copied into it
+pub struct TextureArray {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
+    format: wgpu::TextureFormat,
+    layer_size: u32,
+    layer_capacity: u32,
+    num_layers: u32,
 }
 
-use std::boxed::Box;
+// The part of the texture array a loaded texture occupies
+// Texture coordinates in [0,1] are mapped into the region with uv_offset + uv*uv_scale
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TextureRegion {
+    pub layer: u32,
+    pub uv_offset: [f32; 2],
+    pub uv_scale: [f32; 2],
+}
 
-impl Texture {
-    pub fn from_bytes(device: &wgpu::Device, bytes: &[u8], label: &str) -> Result<(Box<Self>, wgpu::CommandBuffer), failure::Error> {
-        let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, &img, Some(label))
-    }
-
-    pub fn from_image(device: &wgpu::Device, img: &image::DynamicImage, label: Option<&str>) -> Result<(Box<Self>, wgpu::CommandBuffer), failure::Error> {
-        let mut _image_data: Option<image::RgbaImage> = None; // Just to keep the image data alive for rgba in case the image needs to be converted
-        let rgba = match img {
-            image::DynamicImage::ImageRgba8(x) => x,
-            _ => {
-                _image_data = Some(img.to_rgba());
-                match _image_data {
-                    Some(ref x) => x,
-                    None => panic!(),
-                }
-            },
-        };
-        let dimensions = img.dimensions();
+impl TextureRegion {
+    pub fn map_tex_coords(&self, tex_coords: &mut [f32; 4]) {
+        tex_coords[0] = self.uv_offset[0] + tex_coords[0]*self.uv_scale[0];
+        tex_coords[1] = self.uv_offset[1] + tex_coords[1]*self.uv_scale[1];
+    }
+}
 
-        let size = wgpu::Extent3d {
-            width: dimensions.0,
-            height: dimensions.1,
-            depth: 1,
-        };
-        let texture = device.create_texture(
+// The samplers bound at set 1 in shader.fs, in binding order
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Sampler {
+    ClampNearest,
+    RepeatNearest,
+    MirrorRepeatNearest,
+    ClampLinear,
+    RepeatLinear,
+    MirrorRepeatLinear,
+}
+
+impl Sampler {
+    pub fn get_address_mode(self) -> wgpu::AddressMode {
+        match self {
+            Sampler::ClampNearest | Sampler::ClampLinear => wgpu::AddressMode::ClampToEdge,
+            Sampler::RepeatNearest | Sampler::RepeatLinear => wgpu::AddressMode::Repeat,
+            Sampler::MirrorRepeatNearest | Sampler::MirrorRepeatLinear => wgpu::AddressMode::MirrorRepeat,
+        }
+    }
+
+    pub fn get_filter_mode(self) -> wgpu::FilterMode {
+        match self {
+            Sampler::ClampNearest | Sampler::RepeatNearest | Sampler::MirrorRepeatNearest => wgpu::FilterMode::Nearest,
+            Sampler::ClampLinear | Sampler::RepeatLinear | Sampler::MirrorRepeatLinear => wgpu::FilterMode::Linear,
+        }
+    }
+
+    pub fn from_binding(binding: u32) -> Self {
+        match binding {
+            0 => Sampler::ClampNearest,
+            1 => Sampler::RepeatNearest,
+            2 => Sampler::MirrorRepeatNearest,
+            4 => Sampler::RepeatLinear,
+            5 => Sampler::MirrorRepeatLinear,
+            _ => Sampler::ClampLinear,
+        }
+    }
+}
+
+// Limit every backend supports for the number of layers in an array texture
+pub const MAX_TEXTURE_LAYERS: u32 = 256;
+
+impl TextureArray {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, layer_size: u32, layer_capacity: u32) -> Self {
+        let texture = Self::create_texture(device, format, layer_size, layer_capacity);
+        let view = Self::create_view(&texture, format, layer_capacity);
+
+        Self {
+            texture,
+            view,
+            format,
+            layer_size,
+            layer_capacity,
+            num_layers: 0,
+        }
+    }
+
+    fn create_texture(device: &wgpu::Device, format: wgpu::TextureFormat, layer_size: u32, layer_capacity: u32) -> wgpu::Texture {
+        device.create_texture(
             &wgpu::TextureDescriptor {
-                label,
-                size,
-                array_layer_count: 1,
+                label: Some("texture_array"),
+                size: wgpu::Extent3d {
+                    width: layer_size,
+                    height: layer_size,
+                    depth: 1,
+                },
+                array_layer_count: layer_capacity,
                 mip_level_count: 1,
                 sample_count: 1,
                 dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba8UnormSrgb,
-                usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+                format,
+                usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST | wgpu::TextureUsage::COPY_SRC,
             },
-        );
+        )
+    }
 
-        let buffer = device.create_buffer_with_data(
-            &rgba,
-            wgpu::BufferUsage::COPY_SRC,
-        );
+    fn create_view(texture: &wgpu::Texture, format: wgpu::TextureFormat, layer_capacity: u32) -> wgpu::TextureView {
+        texture.create_view(
+            &wgpu::TextureViewDescriptor {
+                format,
+                dimension: wgpu::TextureViewDimension::D2Array,
+                aspect: wgpu::TextureAspect::All,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                array_layer_count: layer_capacity,
+            }
+        )
+    }
 
+    pub fn get_layer_size(&self) -> u32 {
+        self.layer_size
+    }
+
+    // Images are rgba, Bgra formats need the red and blue channels swapped before they are uploaded
+    fn to_texture_bytes(&self, rgba: &image::RgbaImage) -> Vec<u8> {
+        let mut bytes = rgba.to_vec();
+        if matches!(self.format, wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb) {
+            for pixel in bytes.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        bytes
+    }
+
+    // Decodes the image and puts it into a new layer
+    // Returns the region of the image and the command buffer that uploads it; it has to be submitted before the region is used
+    pub fn add_layer_from_bytes(&mut self, device: &wgpu::Device, bytes: &[u8]) -> Result<(TextureRegion, wgpu::CommandBuffer), RendererError> {
+        let img = image::load_from_memory(bytes)?;
+        self.add_layer(device, &img)
+    }
+
+    // Images keep their size, they are copied into the top left corner of the layer and can't be bigger than it
+    // When the array is full it is recreated with more layers; the view changes so bind groups using it have to be recreated
+    pub fn add_layer(&mut self, device: &wgpu::Device, img: &image::DynamicImage) -> Result<(TextureRegion, wgpu::CommandBuffer), RendererError> {
+        let (width, height) = img.dimensions();
+        if width == 0 || height == 0 || width > self.layer_size || height > self.layer_size {
+            return Err(RendererError::InvalidTextureSize { width, height, layer_size: self.layer_size });
+        }
+        if self.num_layers == MAX_TEXTURE_LAYERS {
+            return Err(RendererError::TooManyTextures);
+        }
         let mut encoder = device.create_command_encoder(
             &wgpu::CommandEncoderDescriptor {
-                label: Some("texture buffer copy encoder"),
+                label: Some("texture array upload encoder"),
             },
         );
 
+        if self.num_layers == self.layer_capacity {
+            self.grow(device, &mut encoder);
+        }
+        let layer = self.num_layers;
+        self.num_layers += 1;
+
+        // The edge pixels are repeated into the texels next to the image, so linear filtering at its edge doesn't blend in the rest of the layer
+        let rgba = img.to_rgba();
+        let padded = image::RgbaImage::from_fn((width + 1).min(self.layer_size), (height + 1).min(self.layer_size), |x, y| {
+            *rgba.get_pixel(x.min(width-1), y.min(height-1))
+        });
+        self.write_region(device, &mut encoder, layer, 0, 0, &padded);
+
+        let region = TextureRegion {
+            layer,
+            uv_offset: [0.0, 0.0],
+            uv_scale: [width as f32 / self.layer_size as f32, height as f32 / self.layer_size as f32],
+        };
+        Ok((region, encoder.finish()))
+    }
+
+    // Copies an image into part of a layer without resizing it
+    pub fn write_region(&self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, layer: u32, x: u32, y: u32, rgba: &image::RgbaImage) {
+        let buffer = device.create_buffer_with_data(
+            &self.to_texture_bytes(rgba),
+            wgpu::BufferUsage::COPY_SRC,
+        );
+
         encoder.copy_buffer_to_texture(
             wgpu::BufferCopyView {
                 buffer: &buffer,
                 offset: 0,
-                bytes_per_row: 4*dimensions.0,
-                rows_per_image: dimensions.1
+                bytes_per_row: 4*rgba.width(),
+                rows_per_image: rgba.height(),
             },
             wgpu::TextureCopyView {
-                texture: &texture,
+                texture: &self.texture,
                 mip_level: 0,
-                array_layer: 0,
-                origin: wgpu::Origin3d::ZERO,
+                array_layer: layer,
+                origin: wgpu::Origin3d { x, y, z: 0 },
+            },
+            wgpu::Extent3d {
+                width: rgba.width(),
+                height: rgba.height(),
+                depth: 1,
             },
-            size,
         );
+    }
 
-        let cmd_buffer = encoder.finish();
+    fn grow(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
+        let new_capacity = (self.layer_capacity*2).min(MAX_TEXTURE_LAYERS);
+        let texture = Self::create_texture(device, self.format, self.layer_size, new_capacity);
 
-        let view = texture.create_default_view();
+        for layer in 0..self.num_layers {
+            encoder.copy_texture_to_texture(
+                wgpu::TextureCopyView {
+                    texture: &self.texture,
+                    mip_level: 0,
+                    array_layer: layer,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                wgpu::TextureCopyView {
+                    texture: &texture,
+                    mip_level: 0,
+                    array_layer: layer,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                wgpu::Extent3d {
+                    width: self.layer_size,
+                    height: self.layer_size,
+                    depth: 1,
+                },
+            );
+        }
 
-        Ok(( Box::new(Self{texture, view}), cmd_buffer ))
+        self.view = Self::create_view(&texture, self.format, new_capacity);
+        self.texture = texture;
+        self.layer_capacity = new_capacity;
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLERS: [Sampler; 6] = [
+        Sampler::ClampNearest,
+        Sampler::RepeatNearest,
+        Sampler::MirrorRepeatNearest,
+        Sampler::ClampLinear,
+        Sampler::RepeatLinear,
+        Sampler::MirrorRepeatLinear,
+    ];
+
+    #[test]
+    fn binding_round_trip() {
+        for sampler in &SAMPLERS {
+            assert_eq!(Sampler::from_binding(*sampler as u32), *sampler);
+        }
+    }
+
+    #[test]
+    fn unknown_binding_is_default_sampler() {
+        assert_eq!(Sampler::from_binding(6), Sampler::ClampLinear);
+        assert_eq!(Sampler::from_binding(u32::MAX), Sampler::ClampLinear);
+    }
+
+    #[test]
+    fn modes_are_distinct() {
+        let modes: std::collections::HashSet<_> = SAMPLERS.iter()
+            .map(|sampler| (sampler.get_address_mode() as u32, sampler.get_filter_mode() as u32))
+            .collect();
+        assert_eq!(modes.len(), SAMPLERS.len());
+    }
+}
@@ -0,0 +1,181 @@
+// mod texture;
+use super::texture::{TextureArray, TextureRegion};
+
+// mod renderer;
+use super::renderer::RendererError;
+
+// Border kept around every image so linear filtering doesn't pick up its neighbours
+// The border is filled with the image's edge pixels
+const PADDING: u32 = 1;
+
+// A row of images in a page; every image in it is at most as high as the shelf
+struct Shelf {
+    y: u32,
+    height: u32,
+    next_x: u32,
+}
+
+// One layer of the texture array that is shared by many small images
+struct AtlasPage {
+    layer: u32,
+    shelves: Vec<Shelf>,
+    next_shelf_y: u32,
+}
+
+impl AtlasPage {
+    fn new(layer: u32) -> Self {
+        Self {
+            layer,
+            shelves: vec![],
+            next_shelf_y: 0,
+        }
+    }
+
+    // Shelf packing: use the lowest fitting shelf that doesn't waste too much height, otherwise open a new shelf
+    fn allocate(&mut self, width: u32, height: u32, page_size: u32) -> Option<(u32, u32)> {
+        let best_shelf = self.shelves.iter_mut()
+            .filter(|shelf| shelf.height >= height && shelf.height <= height*2 && shelf.next_x + width <= page_size)
+            .min_by_key(|shelf| shelf.height);
+        if let Some(shelf) = best_shelf {
+            let x = shelf.next_x;
+            shelf.next_x += width;
+            return Some((x, shelf.y));
+        }
+
+        if self.next_shelf_y + height > page_size {
+            return None;
+        }
+        let y = self.next_shelf_y;
+        self.next_shelf_y += height;
+        self.shelves.push(Shelf {
+            y,
+            height,
+            next_x: width,
+        });
+        Some((0, y))
+    }
+}
+
+// Packs small images into pages of the renderer's texture array
+// Every page is one layer, so a batch only needs one layer per page instead of one per image
+pub struct TextureAtlas {
+    pages: Vec<AtlasPage>,
+}
+
+impl TextureAtlas {
+    pub fn new() -> Self {
+        Self {
+            pages: vec![],
+        }
+    }
+
+    // Images that don't fit into a page with their padding have to get a layer of their own
+    pub fn fits(&self, texture_array: &TextureArray, width: u32, height: u32) -> bool {
+        let page_size = texture_array.get_layer_size();
+        width + 2*PADDING <= page_size && height + 2*PADDING <= page_size
+    }
+
+    // Copies the image into a page, a new page is added to the texture array when no page has room left
+    // Returns the region of the image and the command buffers that upload it; they have to be submitted in order before the region is used
+    // Fails for empty images and images that don't fit
+    pub fn add_image(&mut self, device: &wgpu::Device, texture_array: &mut TextureArray, img: &image::DynamicImage) -> Result<(TextureRegion, Vec<wgpu::CommandBuffer>), RendererError> {
+        let img = img.to_rgba();
+        let (width, height) = img.dimensions();
+        let page_size = texture_array.get_layer_size();
+        if width == 0 || height == 0 || !self.fits(texture_array, width, height) {
+            return Err(RendererError::InvalidAtlasImageSize {
+                width,
+                height,
+                max_size: page_size.saturating_sub(2*PADDING),
+            });
+        }
+
+        let padded_width = width + 2*PADDING;
+        let padded_height = height + 2*PADDING;
+
+        let mut cmd_buffers = vec![];
+        let mut allocation = None;
+        for page in &mut self.pages {
+            if let Some((x, y)) = page.allocate(padded_width, padded_height, page_size) {
+                allocation = Some((page.layer, x, y));
+                break;
+            }
+        }
+        let (layer, x, y) = match allocation {
+            Some(allocation) => allocation,
+            None => {
+                let empty_page = image::DynamicImage::new_rgba8(page_size, page_size);
+                let (page_region, cmd_buffer) = texture_array.add_layer(device, &empty_page)?;
+                let layer = page_region.layer;
+                // Clears the new page, so it has to run before the image is copied into it
+                cmd_buffers.push(cmd_buffer);
+
+                let mut page = AtlasPage::new(layer);
+                let (x, y) = page.allocate(padded_width, padded_height, page_size).unwrap();
+                self.pages.push(page);
+                (layer, x, y)
+            },
+        };
+
+        // Extend the edge pixels of the image into the padding
+        let padded = image::RgbaImage::from_fn(padded_width, padded_height, |px, py| {
+            let sx = (px.max(PADDING) - PADDING).min(width-1);
+            let sy = (py.max(PADDING) - PADDING).min(height-1);
+            *img.get_pixel(sx, sy)
+        });
+
+        let mut encoder = device.create_command_encoder(
+            &wgpu::CommandEncoderDescriptor {
+                label: Some("texture atlas upload encoder"),
+            },
+        );
+        texture_array.write_region(device, &mut encoder, layer, x, y, &padded);
+
+        let region = TextureRegion {
+            layer,
+            uv_offset: [(x + PADDING) as f32 / page_size as f32, (y + PADDING) as f32 / page_size as f32],
+            uv_scale: [width as f32 / page_size as f32, height as f32 / page_size as f32],
+        };
+
+        cmd_buffers.push(encoder.finish());
+
+        Ok((region, cmd_buffers))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocate_fills_shelf_from_the_left() {
+        let mut page = AtlasPage::new(0);
+        assert_eq!(page.allocate(10, 8, 32), Some((0, 0)));
+        assert_eq!(page.allocate(10, 8, 32), Some((10, 0)));
+        // Lower images go into the same shelf as long as they don't waste more than half of it
+        assert_eq!(page.allocate(10, 4, 32), Some((20, 0)));
+        assert_eq!(page.shelves.len(), 1);
+    }
+
+    #[test]
+    fn allocate_opens_new_shelves() {
+        let mut page = AtlasPage::new(0);
+        assert_eq!(page.allocate(20, 8, 32), Some((0, 0)));
+        // Too wide for the rest of the first shelf
+        assert_eq!(page.allocate(20, 8, 32), Some((0, 8)));
+        // Too low for both shelves without wasting space
+        assert_eq!(page.allocate(4, 3, 32), Some((0, 16)));
+        // The lowest fitting shelf is used
+        assert_eq!(page.allocate(4, 3, 32), Some((4, 16)));
+        assert_eq!(page.allocate(4, 8, 32), Some((20, 0)));
+        assert_eq!(page.shelves.len(), 3);
+    }
+
+    #[test]
+    fn allocate_fails_when_page_is_full() {
+        let mut page = AtlasPage::new(0);
+        assert_eq!(page.allocate(32, 16, 32), Some((0, 0)));
+        assert_eq!(page.allocate(32, 16, 32), Some((0, 16)));
+        assert_eq!(page.allocate(1, 1, 32), None);
+    }
+}
@@ -22,4 +22,13 @@ impl Uniforms {
     pub fn set_camera(&mut self, camera: &Camera) {
         self.camera_transform = Matrix4::from(camera.get_view_matrix());
     }
+
+    // The shader only uses the top left 3x3 of the matrix (see shader.vs)
+    pub fn get_camera_transform(&self) -> Matrix3<f32> {
+        Matrix3::from_cols(
+            self.camera_transform.x.truncate(),
+            self.camera_transform.y.truncate(),
+            self.camera_transform.z.truncate(),
+        )
+    }
 }
\ No newline at end of file
@@ -1,4 +1,11 @@
 use super::point::Point;
+use super::texture::Sampler;
+
+// Sampler used by vertices that don't pick one
+pub const DEFAULT_SAMPLER: u32 = Sampler::ClampLinear as u32;
+
+// Transform of vertices that are drawn where they are, the renderer sets the id of transformed draws
+pub const IDENTITY_TRANSFORM_ID: u32 = 0;
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug)]
@@ -8,6 +15,10 @@ pub struct Vertex {
     // Otherwise, tex_coords_or_color is texture coordinates and color is not used
     pub texture_binding: i32,
     pub tex_coords_or_color: [f32; 4],
+    // Binding of the sampler in shader.fs, see texture::Sampler
+    pub sampler: u32,
+    // Index into the transforms that shader.vs applies before the camera transform
+    pub transform_id: u32,
 }
 unsafe impl bytemuck::Pod for Vertex {}
 unsafe impl bytemuck::Zeroable for Vertex {}
@@ -34,6 +45,16 @@ impl Vertex {
                     shader_location: 2,
                     format: wgpu::VertexFormat::Float4,
                 },
+                wgpu::VertexAttributeDescriptor {
+                    offset: (mem::size_of::<[f32; 2]>()+mem::size_of::<i32>()+mem::size_of::<[f32; 4]>()) as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Uint,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: (mem::size_of::<[f32; 2]>()+mem::size_of::<i32>()+mem::size_of::<[f32; 4]>()+mem::size_of::<u32>()) as wgpu::BufferAddress,
+                    shader_location: 4,
+                    format: wgpu::VertexFormat::Uint,
+                },
             ],
         }
     }
@@ -0,0 +1,37 @@
+use super::clip::ScissorRect;
+
+// The part of the frame that draws are mapped into, in pixels like a ScissorRect
+// Shapes are placed inside it as if it was the whole frame, so cameras should use its aspect ratio
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Viewport {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Viewport {
+    pub fn get_aspect_ratio(self) -> f32 {
+        self.width as f32 / self.height as f32
+    }
+
+    // Moves a rectangle that is relative to the viewport's top left corner into the frame
+    pub fn to_frame_rect(self, rect: ScissorRect) -> ScissorRect {
+        ScissorRect {
+            x: self.x + rect.x,
+            y: self.y + rect.y,
+            width: rect.width,
+            height: rect.height,
+        }
+    }
+
+    // Nothing is drawn outside of the viewport
+    pub fn to_scissor_rect(self) -> ScissorRect {
+        ScissorRect {
+            x: self.x,
+            y: self.y,
+            width: self.width,
+            height: self.height,
+        }
+    }
+}
@@ -36,8 +36,9 @@ pub struct Window {
 }
 
 impl Window {
-    pub fn new<T>(event_loop: &EventLoop<T>, format: wgpu::TextureFormat) -> Self {
-        let winit_window = winit::window::WindowBuilder::new().build(event_loop).unwrap();
+    // Every window has a swap chain of its own, the renderer draws into whichever window's frame it is given
+    pub fn new<T>(event_loop: &EventLoop<T>, title: &str, format: wgpu::TextureFormat) -> Self {
+        let winit_window = winit::window::WindowBuilder::new().with_title(title).build(event_loop).unwrap();
         let size = winit_window.inner_size();
 
         let surface = wgpu::Surface::create(&winit_window);
@@ -94,7 +95,7 @@ impl Window {
         }
     }
 
-    pub fn get_aspect_ratio(&self) -> f32 {
-        self.size.width as f32 / self.size.height as f32
+    pub fn get_size(&self) -> (u32, u32) {
+        (self.size.width, self.size.height)
     }
 }
\ No newline at end of file